@@ -25,3 +25,10 @@ pub fn parse_mut(lines: &mut Lines<impl BufRead>) -> Vec<String> {
     println!("len={}", vs.len());
     vs
 }
+
+/// Like `parse` but keeps going past blank lines, which are kept
+pub fn parse_all(lines: Lines<impl BufRead>) -> Vec<String> {
+    let vs: Vec<String> = lines.map(|l| l.unwrap()).collect();
+    println!("len={}", vs.len());
+    vs
+}
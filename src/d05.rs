@@ -1,8 +1,15 @@
-use std::{io::{BufRead, Lines}, collections::VecDeque};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io::{BufRead, Lines},
+};
 
 use crate::common;
 
-type CrateStack = VecDeque<char>;
+/// Print the stacks drawing after every simulated move
+const PRINT_STEPS: bool = false;
+
+type CrateStack = VecDeque<String>;
 
 struct Move {
     cnt: usize,
@@ -10,101 +17,444 @@ struct Move {
     to: usize,
 }
 
+/// How a crane lifts crates off a stack.
+#[derive(Clone, Copy)]
+enum CraneModel {
+    /// CrateMover 9000, one crate per lift
+    OneAtATime,
+    /// CrateMover 9001, the whole requested slice in one lift
+    WholeStack,
+    /// At most `K` crates per lift, each lift keeps the crate order.
+    /// A capacity of 0 is treated as 1.
+    Capacity(usize),
+}
+
+impl CraneModel {
+    /// Max crates moved in a single lift, `None` means unlimited
+    fn lift_cap(&self) -> Option<usize> {
+        match self {
+            CraneModel::OneAtATime => Some(1),
+            CraneModel::WholeStack => None,
+            CraneModel::Capacity(k) => Some((*k).max(1)),
+        }
+    }
+}
+
+impl Display for CraneModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CraneModel::OneAtATime => write!(f, "mover 9000"),
+            CraneModel::WholeStack => write!(f, "mover 9001"),
+            CraneModel::Capacity(k) => write!(f, "capacity {}", k),
+        }
+    }
+}
+
+/// Outcome of running a move list with one crane
+struct SimResult {
+    top: String,
+    /// Number of lifts the crane performed
+    lifts: usize,
+}
+
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "move {} from {} to {}",
+            self.cnt,
+            self.from + 1,
+            self.to + 1
+        )
+    }
+}
+
+/// Reason a move can't be executed against the current stacks.
+///
+/// `step` is the 1-based position of the move in the move list.
+enum MoveError {
+    UnknownStack {
+        step: usize,
+        stack: usize,
+        stack_cnt: usize,
+    },
+    NotEnoughCrates {
+        step: usize,
+        stack: usize,
+        requested: usize,
+        available: usize,
+    },
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::UnknownStack {
+                step,
+                stack,
+                stack_cnt,
+            } => write!(
+                f,
+                "step #{}: stack {} does not exist (only {} stacks)",
+                step,
+                stack + 1,
+                stack_cnt
+            ),
+            MoveError::NotEnoughCrates {
+                step,
+                stack,
+                requested,
+                available,
+            } => write!(
+                f,
+                "step #{}: cannot move {} crates from stack {}, it only holds {}",
+                step,
+                requested,
+                stack + 1,
+                available
+            ),
+        }
+    }
+}
+
+/// Reason the puzzle input can't be parsed.
+///
+/// `line` is the 1-based input line, `col` the 0-based char column.
+enum ParseError {
+    MissingFooter,
+    BadFooter { line: usize },
+    StrayChar { line: usize, col: usize },
+    UnclosedCrate { line: usize, col: usize },
+    EmptyLabel { line: usize, col: usize },
+    UnknownColumn { line: usize, col: usize },
+    AmbiguousColumn { line: usize, col: usize },
+    DuplicateCrate { line: usize, stack: usize },
+    FloatingCrate { line: usize, stack: usize },
+    BadMove { line: usize },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MissingFooter => write!(f, "no stack number footer line found"),
+            ParseError::BadFooter { line } => {
+                write!(f, "line {}: footer must number stacks 1..n in order", line)
+            }
+            ParseError::StrayChar { line, col } => {
+                write!(
+                    f,
+                    "line {}:{}: unexpected char outside of a crate",
+                    line, col
+                )
+            }
+            ParseError::UnclosedCrate { line, col } => {
+                write!(f, "line {}:{}: crate is missing its closing `]`", line, col)
+            }
+            ParseError::EmptyLabel { line, col } => {
+                write!(f, "line {}:{}: crate has an empty label", line, col)
+            }
+            ParseError::UnknownColumn { line, col } => write!(
+                f,
+                "line {}:{}: crate is not above any numbered stack",
+                line, col
+            ),
+            ParseError::AmbiguousColumn { line, col } => write!(
+                f,
+                "line {}:{}: crate spans more than one numbered stack",
+                line, col
+            ),
+            ParseError::DuplicateCrate { line, stack } => write!(
+                f,
+                "line {}: more than one crate drawn for stack {}",
+                line,
+                stack + 1
+            ),
+            ParseError::FloatingCrate { line, stack } => write!(
+                f,
+                "line {}: stack {} has a gap below its crate",
+                line,
+                stack + 1
+            ),
+            ParseError::BadMove { line } => {
+                write!(f, "line {}: expected `move <n> from <a> to <b>`", line)
+            }
+        }
+    }
+}
+
 pub fn run(input: Lines<impl BufRead>) {
     let strs = common::parse(input);
 
-    let (data, moves) = process(&strs);
-    let res = compute(&mut data.clone(), &moves, false);
-    let res_2 = compute(&mut data.clone(), &moves, true);
+    let (data, moves) = match process(&strs) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("invalid input: {}", e);
+            return;
+        }
+    };
+
+    let cranes = [
+        CraneModel::OneAtATime,
+        CraneModel::WholeStack,
+        CraneModel::Capacity(3),
+    ];
+    for crane in cranes {
+        let mut ds = data.clone();
+        match compute(&mut ds, &moves, crane) {
+            Ok(res) => {
+                println!("top items ({})={} | lifts={}", crane, res.top, res.lifts);
+                let drawing = render(&ds);
+                println!("{}", drawing);
 
-    println!("top items={}", res);
-    println!("top items (mover 9001)={}", res_2);
+                let lines: Vec<String> = drawing.lines().map(String::from).collect();
+                let round_trip = process(&lines).is_ok_and(|(back, _)| back == ds);
+                println!("round-trip: {}", if round_trip { "ok" } else { "mismatch" });
+            }
+            Err(e) => println!("invalid move ({}): {}", crane, e),
+        }
+    }
 }
 
-fn compute(ds: &mut [CrateStack], mvs: &[Move], use_mover_9001: bool) -> String {
+fn compute(ds: &mut [CrateStack], mvs: &[Move], crane: CraneModel) -> Result<SimResult, MoveError> {
     let mut rs = String::from("");
+    let mut lifts = 0;
 
     // sim
-    for m in mvs {
-        let cf = &mut ds[m.from];
-        let mut sl_cf = vec![' '; m.cnt];
-        for i in 0..m.cnt {
-            if use_mover_9001 {
-                sl_cf[(m.cnt - 1) - i] = cf.pop_back().unwrap();
-            } else {
-                sl_cf[i] = cf.pop_back().unwrap();
-            }
+    for (i, m) in mvs.iter().enumerate() {
+        validate(ds, m, i + 1)?;
+
+        let cap = crane.lift_cap().unwrap_or(m.cnt);
+        let mut left = m.cnt;
+        while left > 0 {
+            let n = left.min(cap);
+            let cf = &mut ds[m.from];
+            let sl_cf = cf.split_off(cf.len() - n);
+            ds[m.to].extend(sl_cf);
+
+            left -= n;
+            lifts += 1;
         }
 
-        let ct = &mut ds[m.to];
-        ct.extend(sl_cf);
+        if PRINT_STEPS {
+            println!("{}\n{}\n", m, render(ds));
+        }
     }
 
-    for cs in ds {
+    for cs in ds.iter() {
         let last_ch = cs.back();
         if let Some(ch) = last_ch {
-            rs.push(*ch);
+            rs.push_str(ch);
+        }
+    }
+
+    Ok(SimResult { top: rs, lifts })
+}
+
+fn validate(ds: &[CrateStack], m: &Move, step: usize) -> Result<(), MoveError> {
+    for stack in [m.from, m.to] {
+        if stack >= ds.len() {
+            return Err(MoveError::UnknownStack {
+                step,
+                stack,
+                stack_cnt: ds.len(),
+            });
         }
     }
 
-    rs
+    let available = ds[m.from].len();
+    if m.cnt > available {
+        return Err(MoveError::NotEnoughCrates {
+            step,
+            stack: m.from,
+            requested: m.cnt,
+            available,
+        });
+    }
+
+    Ok(())
+}
+
+/// Draw the stacks using the same layout as the puzzle input,
+/// including the numbered footer line.
+///
+/// Every cell is as wide as the longest label, and every line has the same
+/// width, so the output can be fed back into `process`.
+fn render(ds: &[CrateStack]) -> String {
+    let height = ds.iter().map(|cs| cs.len()).max().unwrap_or(0);
+    let label_w = ds
+        .iter()
+        .flat_map(|cs| cs.iter().map(|c| c.chars().count()))
+        .max()
+        .unwrap_or(1);
+    let cell_w = label_w + 2;
+    let mut out: Vec<String> = vec![];
+
+    for h in (0..height).rev() {
+        let row: Vec<String> = ds
+            .iter()
+            .map(|cs| match cs.get(h) {
+                Some(c) => format!("[{:<w$}]", c, w = label_w),
+                None => " ".repeat(cell_w),
+            })
+            .collect();
+        out.push(row.join(" "));
+    }
+
+    let footer: Vec<String> = (1..=ds.len())
+        .map(|i| format!("{:^w$}", i, w = cell_w))
+        .collect();
+    out.push(footer.join(" "));
+
+    out.join("\n")
 }
 
-fn process(lines: &[String]) -> (Vec<CrateStack>, Vec<Move>) {
-    let mut v: Vec<CrateStack> = vec![];
+fn process(lines: &[String]) -> Result<(Vec<CrateStack>, Vec<Move>), ParseError> {
+    let footer_i = lines
+        .iter()
+        .position(|l| {
+            let mut spl = l.split_whitespace().peekable();
+            spl.peek().is_some() && spl.all(|s| s.chars().all(|ch| ch.is_ascii_digit()))
+        })
+        .ok_or(ParseError::MissingFooter)?;
+
+    let cols = parse_footer(&lines[footer_i], footer_i + 1)?;
+    let v = parse_drawing(&lines[..footer_i], &cols)?;
+
     let mut moves: Vec<Move> = vec![];
+    for (i, line) in lines.iter().enumerate().skip(footer_i + 1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        moves.push(parse_move(line).ok_or(ParseError::BadMove { line: i + 1 })?);
+    }
 
-    let mut lit = lines.iter().peekable();
-
-    for line in lit.by_ref() {
-        let mut init = true;
-        let mut cs: Vec<char> = vec![];
-
-        // Parse each stack line
-        let l: Vec<char> = line.chars().collect();
-        let mut li = 1_usize;
-        let l_len = l.len();
-        while li < l_len {
-            let ch = l[li];
-            let bv = ch as u8;
-            if (bv >= 65 && bv <= 90) || bv == 32 {
-                cs.push(ch);
-
-                li += 4;
-            } else {
-                init = false;
-                break;
-            }
+    Ok((v, moves))
+}
+
+/// Returns the char column each stack number is centered on
+fn parse_footer(line: &str, line_no: usize) -> Result<Vec<usize>, ParseError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut cols = vec![];
+
+    let mut ci = 0;
+    while ci < chars.len() {
+        if chars[ci] == ' ' {
+            ci += 1;
+            continue;
         }
 
-        if !init {
-            break;
+        let start = ci;
+        while ci < chars.len() && chars[ci] != ' ' {
+            ci += 1;
         }
+        let num: String = chars[start..ci].iter().collect();
+        if num.parse::<usize>().ok() != Some(cols.len() + 1) {
+            return Err(ParseError::BadFooter { line: line_no });
+        }
+        cols.push(start + (ci - 1 - start) / 2);
+    }
 
-        if v.len() < cs.len() {
-            v.resize(cs.len(), VecDeque::new());
+    Ok(cols)
+}
+
+/// Reads the crates row by row (top to bottom), assigning each `[label]`
+/// to the stack whose footer number lies under it.
+fn parse_drawing(lines: &[String], cols: &[usize]) -> Result<Vec<CrateStack>, ParseError> {
+    let mut v: Vec<CrateStack> = vec![VecDeque::new(); cols.len()];
+
+    for (li, line) in lines.iter().enumerate() {
+        let line_no = li + 1;
+        let chars: Vec<char> = line.chars().collect();
+        let mut row: Vec<Option<String>> = vec![None; cols.len()];
+
+        let mut ci = 0;
+        while ci < chars.len() {
+            match chars[ci] {
+                ' ' => {
+                    ci += 1;
+                    continue;
+                }
+                '[' => {}
+                _ => {
+                    return Err(ParseError::StrayChar {
+                        line: line_no,
+                        col: ci,
+                    })
+                }
+            }
+
+            let start = ci;
+            let end = chars[start..]
+                .iter()
+                .position(|ch| *ch == ']')
+                .map(|p| start + p)
+                .ok_or(ParseError::UnclosedCrate {
+                    line: line_no,
+                    col: start,
+                })?;
+            // `render` pads short labels inside the brackets
+            let label: String = chars[start + 1..end].iter().collect();
+            let label = label.trim();
+            if label.is_empty() {
+                return Err(ParseError::EmptyLabel {
+                    line: line_no,
+                    col: start,
+                });
+            }
+
+            let mut under = cols
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| (start..=end).contains(*c))
+                .map(|(i, _)| i);
+            let stack = under.next().ok_or(ParseError::UnknownColumn {
+                line: line_no,
+                col: start,
+            })?;
+            if under.next().is_some() {
+                return Err(ParseError::AmbiguousColumn {
+                    line: line_no,
+                    col: start,
+                });
+            }
+            if row[stack].is_some() {
+                return Err(ParseError::DuplicateCrate {
+                    line: line_no,
+                    stack,
+                });
+            }
+            row[stack] = Some(label.to_string());
+
+            ci = end + 1;
         }
 
-        for ci in 0..cs.len() {
-            let c = cs[ci];
-            if c != ' ' {
-                v[ci].push_front(c);
+        for (stack, label) in row.into_iter().enumerate() {
+            match label {
+                Some(label) => v[stack].push_front(label),
+                // a crate was drawn above this empty slot
+                None if !v[stack].is_empty() => {
+                    return Err(ParseError::FloatingCrate {
+                        line: line_no,
+                        stack,
+                    })
+                }
+                None => {}
             }
         }
     }
 
-    for line in lit.by_ref() {
-        let mut spl = line.split(" ");
-        spl.next();
-        let cnt = spl.next().unwrap().parse::<usize>().unwrap();
-        spl.next();
-        let from = spl.next().unwrap().parse::<usize>().unwrap() - 1;
-        spl.next();
-        let to = spl.next().unwrap().parse::<usize>().unwrap() - 1;
-
-        let m = Move { cnt, from, to };
-        moves.push(m);
+    Ok(v)
+}
+
+fn parse_move(line: &str) -> Option<Move> {
+    let spl: Vec<&str> = line.split_whitespace().collect();
+    if let ["move", cnt, "from", from, "to", to] = spl[..] {
+        let cnt = cnt.parse::<usize>().ok()?;
+        let from = from.parse::<usize>().ok()?.checked_sub(1)?;
+        let to = to.parse::<usize>().ok()?.checked_sub(1)?;
+
+        return Some(Move { cnt, from, to });
     }
 
-    (v, moves)
+    None
 }
@@ -1,5 +1,6 @@
 use std::{
-    collections::{hash_map, HashMap},
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
     io::{BufRead, Lines},
 };
 
@@ -8,157 +9,589 @@ use crate::common;
 const TOTAL_SIZE: u64 = 70_000_000;
 const NEED_SIZE: u64 = 30_000_000;
 
-/// A representation of file-sys Dir node.
+/// Disk limits used to work out how much space has to be freed
+struct DiskConfig {
+    capacity: u64,
+    required: u64,
+}
+
+impl Default for DiskConfig {
+    fn default() -> Self {
+        DiskConfig {
+            capacity: TOTAL_SIZE,
+            required: NEED_SIZE,
+        }
+    }
+}
+
+/// Reason a disk size CLI arg can't be used
+enum ConfigError {
+    BadCapacity(String),
+    BadRequired(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::BadCapacity(a) => write!(f, "`{}` is not a disk capacity", a),
+            ConfigError::BadRequired(a) => write!(f, "`{}` is not a required size", a),
+        }
+    }
+}
+
+impl DiskConfig {
+    /// Reads `[capacity] [required]` from the extra CLI args,
+    /// missing values fall back to the puzzle defaults
+    fn from_args(args: &[String]) -> Result<Self, ConfigError> {
+        let def = DiskConfig::default();
+        let arg = |i: usize, def: u64, err: fn(String) -> ConfigError| match args.get(i) {
+            Some(a) => a.parse::<u64>().map_err(|_| err(a.clone())),
+            None => Ok(def),
+        };
+
+        Ok(DiskConfig {
+            capacity: arg(0, def.capacity, ConfigError::BadCapacity)?,
+            required: arg(1, def.required, ConfigError::BadRequired)?,
+        })
+    }
+
+    fn to_free(&self, used: u64) -> u64 {
+        self.required
+            .saturating_sub(self.capacity.saturating_sub(used))
+    }
+}
+
+/// Dirs picked for deletion, none of them is inside another one
+struct DeletePlan {
+    dirs: Vec<usize>,
+    freed: u64,
+}
+
+/// Index of the `/` node inside `FileSystem::nodes`
+const ROOT: usize = 0;
+
+enum NodeKind {
+    /// Children are keyed by name, so listings come out sorted
+    Dir {
+        children: BTreeMap<String, usize>,
+    },
+    File {
+        size: u64,
+    },
+}
+
+/// A representation of file-sys node, either a dir or a file.
+///
+/// Nodes only point to their parent by index, the root points to itself.
+struct FsNode {
+    name: String,
+    parent: usize,
+    kind: NodeKind,
+}
+
+impl FsNode {
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, NodeKind::Dir { .. })
+    }
+}
+
+/// In-memory file-sys tree rebuilt from the terminal log.
 ///
-/// Field `size` behavior:
-///  will be a total size from every child nodes.
-struct DirNode {
-    parent_index: usize,
-    size: u64,
-    _name: String,
+/// A child is always pushed after its parent, so walking `nodes` backwards
+/// visits every node before its parent.
+struct FileSystem {
+    nodes: Vec<FsNode>,
+}
+
+impl FileSystem {
+    fn new() -> Self {
+        FileSystem {
+            nodes: vec![FsNode {
+                name: "".into(),
+                parent: ROOT,
+                kind: NodeKind::Dir {
+                    children: BTreeMap::new(),
+                },
+            }],
+        }
+    }
+
+    fn child(&self, dir: usize, name: &str) -> Option<usize> {
+        match &self.nodes[dir].kind {
+            NodeKind::Dir { children } => children.get(name).copied(),
+            NodeKind::File { .. } => None,
+        }
+    }
+
+    /// Adds `node` under `dir` and returns its index
+    fn insert(&mut self, dir: usize, node: FsNode) -> usize {
+        let idx = self.nodes.len();
+        let name = node.name.clone();
+        self.nodes.push(node);
+        if let NodeKind::Dir { children } = &mut self.nodes[dir].kind {
+            children.insert(name, idx);
+        }
+
+        idx
+    }
+
+    /// Returns the existing child dir `name`, creating it when missing
+    fn mkdir(&mut self, dir: usize, name: &str) -> usize {
+        if let Some(idx) = self.child(dir, name) {
+            return idx;
+        }
+
+        let node = FsNode {
+            name: name.into(),
+            parent: dir,
+            kind: NodeKind::Dir {
+                children: BTreeMap::new(),
+            },
+        };
+        self.insert(dir, node)
+    }
+
+    /// Returns the existing child file `name`, creating it when missing
+    fn add_file(&mut self, dir: usize, name: &str, size: u64) -> usize {
+        if let Some(idx) = self.child(dir, name) {
+            return idx;
+        }
+
+        let node = FsNode {
+            name: name.into(),
+            parent: dir,
+            kind: NodeKind::File { size },
+        };
+        self.insert(dir, node)
+    }
+
+    /// Resolves an absolute path like `/a/b.txt`
+    fn lookup(&self, path: &str) -> Option<usize> {
+        path.split('/')
+            .filter(|p| !p.is_empty())
+            .try_fold(ROOT, |dir, name| self.child(dir, name))
+    }
+
+    fn path(&self, idx: usize) -> String {
+        let mut parts = vec![];
+        let mut cur = idx;
+        while cur != ROOT {
+            parts.push(self.nodes[cur].name.as_str());
+            cur = self.nodes[cur].parent;
+        }
+        parts.reverse();
+
+        format!("/{}", parts.join("/"))
+    }
+
+    /// Recursive size of every node, indexed like `nodes`
+    fn sizes(&self) -> Vec<u64> {
+        let mut sizes = vec![0_u64; self.nodes.len()];
+        // bottom up from leaf to its parent (file -> dir -> parent dir)
+        for idx in (0..self.nodes.len()).rev() {
+            if let NodeKind::File { size } = self.nodes[idx].kind {
+                sizes[idx] = size;
+            }
+            if idx != ROOT {
+                sizes[self.nodes[idx].parent] += sizes[idx];
+            }
+        }
+
+        sizes
+    }
+
+    /// Every dir in pre-order, along with the position right after the
+    /// last dir of its subtree
+    fn dirs_preorder(&self) -> (Vec<usize>, Vec<usize>) {
+        let mut order = vec![];
+        let mut end = vec![];
+        self.walk_dirs(ROOT, &mut order, &mut end);
+
+        (order, end)
+    }
+
+    fn walk_dirs(&self, dir: usize, order: &mut Vec<usize>, end: &mut Vec<usize>) {
+        let NodeKind::Dir { children } = &self.nodes[dir].kind else {
+            return;
+        };
+
+        let pos = order.len();
+        order.push(dir);
+        end.push(0);
+        for child in children.values() {
+            self.walk_dirs(*child, order, end);
+        }
+        end[pos] = order.len();
+    }
+
+    /// Every file under `dir`, recursively
+    fn files(&self, dir: usize) -> Vec<usize> {
+        match &self.nodes[dir].kind {
+            NodeKind::Dir { children } => children.values().flat_map(|c| self.files(*c)).collect(),
+            NodeKind::File { .. } => vec![dir],
+        }
+    }
+
+    fn size(&self, idx: usize) -> u64 {
+        self.sizes()[idx]
+    }
+
+    /// Lists a dir the same way the puzzle `ls` output does
+    fn ls(&self, dir: usize) -> Vec<String> {
+        let NodeKind::Dir { children } = &self.nodes[dir].kind else {
+            return vec![];
+        };
+
+        children
+            .iter()
+            .map(|(name, idx)| match self.nodes[*idx].kind {
+                NodeKind::Dir { .. } => format!("dir {}", name),
+                NodeKind::File { size } => format!("{} {}", size, name),
+            })
+            .collect()
+    }
+
+    /// Every dir with its recursive size, biggest first
+    fn du(&self) -> Vec<(String, u64)> {
+        let sizes = self.sizes();
+        let mut report: Vec<(String, u64)> = self
+            .find(|n, _| n.is_dir())
+            .into_iter()
+            .map(|idx| (self.path(idx), sizes[idx]))
+            .collect();
+        report.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        report
+    }
+
+    /// Indexes of every node matching `pred`, which receives the node and
+    /// its recursive size
+    fn find(&self, pred: impl Fn(&FsNode, u64) -> bool) -> Vec<usize> {
+        let sizes = self.sizes();
+        (0..self.nodes.len())
+            .filter(|idx| pred(&self.nodes[*idx], sizes[*idx]))
+            .collect()
+    }
 }
 
-/// A representation of file-sys Dir node.
+/// Bitset of reachable totals, bit `s` set means total `s` is reachable.
 ///
-/// Field `size` behavior:
-///  will be the size of current file.
-struct FileNode {
-    dir_index: usize,
-    size: u64,
-    _name: String,
+/// Totals past `bits` are dropped.
+#[derive(Clone)]
+struct SumSet {
+    words: Vec<u64>,
+    bits: usize,
 }
 
-struct FsInfo {
-    dirs: Vec<DirNode>,
-    files: Vec<FileNode>,
+impl SumSet {
+    fn new(bits: usize) -> Self {
+        SumSet {
+            words: vec![0; bits.div_ceil(64)],
+            bits,
+        }
+    }
+
+    fn set(&mut self, s: usize) {
+        self.words[s / 64] |= 1 << (s % 64);
+    }
+
+    fn get(&self, s: usize) -> bool {
+        s < self.bits && self.words[s / 64] & (1 << (s % 64)) != 0
+    }
+
+    /// Every total plus `by`
+    fn shifted(&self, by: usize) -> SumSet {
+        let mut out = SumSet::new(self.bits);
+        let (ws, bs) = (by / 64, by % 64);
+        for wi in (ws..out.words.len()).rev() {
+            let lo = self.words[wi - ws];
+            let hi = if bs > 0 && wi > ws {
+                self.words[wi - ws - 1] >> (64 - bs)
+            } else {
+                0
+            };
+            out.words[wi] = (lo << bs) | hi;
+        }
+        if !self.bits.is_multiple_of(64) {
+            if let Some(last) = out.words.last_mut() {
+                *last &= (1 << (self.bits % 64)) - 1;
+            }
+        }
+
+        out
+    }
+
+    /// Adds every total of `other`, returning the ones that were missing
+    fn merge(&mut self, other: &SumSet) -> Vec<usize> {
+        let mut added = vec![];
+        for (wi, (w, o)) in self.words.iter_mut().zip(other.words.iter()).enumerate() {
+            let mut new = o & !*w;
+            *w |= new;
+            while new != 0 {
+                added.push(wi * 64 + new.trailing_zeros() as usize);
+                new &= new - 1;
+            }
+        }
+
+        added
+    }
 }
 
-pub fn run(input: Lines<impl BufRead>) {
+/// Finds the non-overlapping dirs whose deletion frees at least `need`,
+/// with the smallest total size.
+///
+/// Dirs are laid out in pre-order, so a dir's subtree is the position range
+/// `k..end[k]`. Walking the positions, a dir is either skipped (go to
+/// `k + 1`) or deleted (jump to `end[k]` with its size added). The reachable
+/// totals only grow along the way, so remembering which dir first reached
+/// each total is enough to rebuild the picked dirs afterwards.
+fn plan_deletion(fs: &FileSystem, need: u64) -> Option<DeletePlan> {
+    let sizes = fs.sizes();
+    let (dirs, end) = fs.dirs_preorder();
+
+    // nothing bigger than the best single dir can be better than it
+    let limit = dirs
+        .iter()
+        .map(|d| sizes[*d])
+        .filter(|sz| *sz >= need)
+        .min()? as usize;
+    let width = limit + 1;
+
+    let mut reach = SumSet::new(width);
+    reach.set(0);
+    // position of the dir that first reached a total
+    let mut parent: Vec<u32> = vec![u32::MAX; width];
+    let mut pending: HashMap<usize, Vec<(usize, SumSet)>> = HashMap::new();
+
+    for k in 0..=dirs.len() {
+        for (from, sums) in pending.remove(&k).unwrap_or_default() {
+            for s in reach.merge(&sums) {
+                parent[s] = from as u32;
+            }
+        }
+
+        if k == dirs.len() {
+            break;
+        }
+        let sz = sizes[dirs[k]] as usize;
+        if sz < width {
+            pending
+                .entry(end[k])
+                .or_default()
+                .push((k, reach.shifted(sz)));
+        }
+    }
+
+    let freed = (need as usize..width).find(|s| reach.get(*s))?;
+    let mut picked = vec![];
+    let mut s = freed;
+    while s > 0 {
+        let k = parent[s] as usize;
+        picked.push(dirs[k]);
+        s -= sizes[dirs[k]] as usize;
+    }
+    picked.reverse();
+
+    Some(DeletePlan {
+        dirs: picked,
+        freed: freed as u64,
+    })
+}
+
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
     let lines = common::parse(input);
+    let cfg = match DiskConfig::from_args(args) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("invalid args: {}", e);
+            return;
+        }
+    };
+
+    let (fs, diags) = replay(&lines);
+    for d in diags.iter() {
+        println!("warn: {}", d);
+    }
+
+    println!("$ ls /");
+    for entry in fs.ls(ROOT) {
+        println!("{}", entry);
+    }
+
+    println!("$ du | head -5");
+    for (path, size) in fs.du().iter().take(5) {
+        println!("{}\t{}", size, path);
+    }
 
-    let mut nds = process(&lines);
-    fill_size(&mut nds);
     // get at most size 100000
-    let sum: u64 = nds
-        .dirs
+    let sizes = fs.sizes();
+    let sum: u64 = fs
+        .find(|n, size| n.is_dir() && size <= 100000)
         .iter()
-        .filter(|d| d.size <= 100000)
-        .fold(0_u64, |acc, d| acc + d.size);
-    let root_size = nds.dirs.first().unwrap().size;
-    let need_delete = NEED_SIZE - (TOTAL_SIZE - root_size);
+        .map(|idx| sizes[*idx])
+        .sum();
+    let root_size = fs.size(ROOT);
+    let need_delete = cfg.to_free(root_size);
     println!("root size: {}", root_size);
-    println!("needed: {}", NEED_SIZE);
-    println!("free: {}", TOTAL_SIZE - root_size);
-    println!("delete: {}", NEED_SIZE - (TOTAL_SIZE - root_size));
+    println!("capacity: {}", cfg.capacity);
+    println!("needed: {}", cfg.required);
+    println!("free: {}", cfg.capacity.saturating_sub(root_size));
+    println!("delete: {}", need_delete);
     println!("sum (<= 100k) size: {sum}");
 
-    let mut deltas: Vec<(u64, u64, bool)> = nds
-        .dirs
-        .iter()
-        .map(|d| {
-            if d.size < need_delete {
-                (0, d.size, false)
-            } else {
-                (d.size - need_delete, d.size, true)
+    let smallest = fs.du().into_iter().rfind(|(_, size)| *size >= need_delete);
+    println!("smallest single dir: {:?}", smallest);
+    if let Some(idx) = smallest.and_then(|(path, _)| fs.lookup(&path)) {
+        println!("  holds {} files", fs.files(idx).len());
+    }
+
+    match plan_deletion(&fs, need_delete) {
+        Some(plan) => {
+            println!("delete plan frees {}:", plan.freed);
+            for dir in plan.dirs {
+                println!("$ rm -r {}\t{}", fs.path(dir), sizes[dir]);
+                for file in fs.files(dir) {
+                    println!("  {}\t{}", fs.path(file), sizes[file]);
+                }
             }
-        })
-        .filter(|tpl| tpl.2)
-        .collect();
-    deltas.sort();
+        }
+        None => println!("delete plan: not enough space even after deleting /"),
+    }
+}
 
-    println!("{:?}", deltas[0])
+/// Something in the terminal log that doesn't add up.
+///
+/// Paths are absolute, as seen by the replay at that point.
+enum DiagKind {
+    /// A file listed again with another size, the first size is kept
+    SizeConflict {
+        path: String,
+        kept: u64,
+        seen: u64,
+    },
+    /// A name listed both as a file and as a dir, the first kind is kept
+    KindConflict {
+        path: String,
+    },
+    /// `cd` into a dir no `ls` has shown yet, the dir is created anyway
+    UnknownDir {
+        path: String,
+    },
+    /// `cd` into a file, the working dir is left as-is
+    NotADir {
+        path: String,
+    },
+    /// `cd ..` while already at `/`
+    AboveRoot,
+    /// Listing output with no `ls` before it
+    OutputWithoutLs,
+    UnknownCommand {
+        cmd: String,
+    },
+    BadLine,
 }
 
-fn fill_size(info: &mut FsInfo) {
-    // bottom up from leaf to its parent (file -> dir -> parent dir)
-    let vf: &Vec<FileNode> = info.files.as_ref();
-    for file in vf {
-        let mut par = file.dir_index;
-        let mut prev_par = info.dirs.len() + 1;
-        while prev_par != par {
-            let mut pref = &mut info.dirs[par];
-            pref.size += file.size;
+struct Diagnostic {
+    /// 1-based line in the log
+    line: usize,
+    kind: DiagKind,
+}
 
-            prev_par = par;
-            par = pref.parent_index
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            DiagKind::SizeConflict { path, kept, seen } => write!(
+                f,
+                "{} listed with size {} but was {} before",
+                path, seen, kept
+            ),
+            DiagKind::KindConflict { path } => {
+                write!(f, "{} listed both as a file and as a dir", path)
+            }
+            DiagKind::UnknownDir { path } => write!(f, "cd into unlisted dir {}", path),
+            DiagKind::NotADir { path } => write!(f, "cd into file {}", path),
+            DiagKind::AboveRoot => write!(f, "cd .. above /"),
+            DiagKind::OutputWithoutLs => write!(f, "listing output without ls"),
+            DiagKind::UnknownCommand { cmd } => write!(f, "unknown command `{}`", cmd),
+            DiagKind::BadLine => write!(f, "unreadable line"),
         }
     }
 }
 
-fn process(lines: &[String]) -> FsInfo {
-    let mut vd: Vec<DirNode> = vec![];
-    let mut vf: Vec<FileNode> = vec![];
-
-    let mut stack: Vec<String> = vec![];
-    let mut name_idx: HashMap<String, usize> = hash_map::HashMap::new();
-
-    vd.push(DirNode {
-        _name: "".into(),
-        parent_index: 0,
-        size: 0,
-    });
-    name_idx.insert("".into(), 0);
-
-    for line in lines {
-        let l = line.split(" ");
-        let l: Vec<&str> = l.collect();
+/// Replays the terminal log into a file-sys tree.
+///
+/// The replay never stops on a bad line, it records a diagnostic and keeps
+/// going with the closest sane state.
+fn replay(lines: &[String]) -> (FileSystem, Vec<Diagnostic>) {
+    let mut fs = FileSystem::new();
+    let mut diags = vec![];
+    let mut cwd = ROOT;
+    let mut in_ls = false;
 
-        if l[0].starts_with("$") {
-            // handle command
-            if l[1] != "cd" {
-                continue;
-            }
+    for (i, line) in lines.iter().enumerate() {
+        let mut diag = |kind: DiagKind| diags.push(Diagnostic { line: i + 1, kind });
+        let l: Vec<&str> = line.split(' ').collect();
 
-            let name = l[2];
-            if name == ".." {
-                stack.pop();
-            } else {
-                if name == "/" {
-                    stack.push("".into());
-                } else {
-                    stack.push(name.into());
+        match l[..] {
+            ["$", "ls"] => in_ls = true,
+            ["$", "cd", target] => {
+                in_ls = false;
+                match target {
+                    "/" => cwd = ROOT,
+                    ".." if cwd == ROOT => diag(DiagKind::AboveRoot),
+                    ".." => cwd = fs.nodes[cwd].parent,
+                    name => match fs.child(cwd, name) {
+                        Some(idx) if fs.nodes[idx].is_dir() => cwd = idx,
+                        Some(idx) => diag(DiagKind::NotADir { path: fs.path(idx) }),
+                        None => {
+                            cwd = fs.mkdir(cwd, name);
+                            diag(DiagKind::UnknownDir { path: fs.path(cwd) });
+                        }
+                    },
                 }
             }
-        } else if l[0].starts_with("dir") {
-            // handle directory
-            let name = l[1];
-            let kp = stack.join("/");
-            let k = kp.clone() + "/" + name;
-
-            if !name_idx.contains_key(&k) {
-                let kpi = name_idx.get(&kp).unwrap();
-
-                vd.push(DirNode {
-                    parent_index: *kpi,
-                    _name: k.clone(),
-                    size: 0,
+            ["$", ..] => {
+                in_ls = false;
+                diag(DiagKind::UnknownCommand {
+                    cmd: l[1..].join(" "),
                 });
-
-                name_idx.insert(k, vd.len() - 1);
             }
-        } else {
-            // handle file
-            let name = l[1];
-            let sz = l[0].parse::<u64>().unwrap();
-            let kp = stack.join("/");
-            let k = kp.clone() + "/" + name;
-
-            if !name_idx.contains_key(&k) {
-                let kpi = name_idx.get(&kp).unwrap();
-
-                vf.push(FileNode {
-                    dir_index: *kpi,
-                    _name: k.clone(),
-                    size: sz,
-                });
+            [_, _] if !in_ls => diag(DiagKind::OutputWithoutLs),
+            ["dir", name] => match fs.child(cwd, name) {
+                Some(idx) if !fs.nodes[idx].is_dir() => {
+                    diag(DiagKind::KindConflict { path: fs.path(idx) })
+                }
+                _ => {
+                    fs.mkdir(cwd, name);
+                }
+            },
+            [sz, name] => {
+                let Ok(sz) = sz.parse::<u64>() else {
+                    diag(DiagKind::BadLine);
+                    continue;
+                };
 
-                name_idx.insert(k, vd.len() - 1);
+                match fs.child(cwd, name).map(|idx| (idx, &fs.nodes[idx].kind)) {
+                    None => {
+                        fs.add_file(cwd, name, sz);
+                    }
+                    Some((idx, NodeKind::File { size })) if *size != sz => {
+                        diag(DiagKind::SizeConflict {
+                            path: fs.path(idx),
+                            kept: *size,
+                            seen: sz,
+                        })
+                    }
+                    Some((_, NodeKind::File { .. })) => {}
+                    Some((idx, NodeKind::Dir { .. })) => {
+                        diag(DiagKind::KindConflict { path: fs.path(idx) })
+                    }
+                }
             }
+            _ => diag(DiagKind::BadLine),
         }
     }
 
-    FsInfo {
-        dirs: vd,
-        files: vf,
-    }
+    (fs, diags)
 }
@@ -2,127 +2,209 @@ use std::io::{BufRead, Lines};
 
 use crate::common;
 
-pub fn run(input: Lines<impl BufRead>) {
-    let strs = common::parse(input);
-    let (vcnt, max_sc) = process(&strs);
-
-    println!("count = {}", vcnt);
-    println!("max scenic = {}", max_sc);
+/// Heatmap shades, from lowest to highest scenic score
+const HEAT_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Up, right, down, left as (row, col) steps
+const DIRS: [(isize, isize); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+
+/// Where the trees are looked at from
+enum Viewpoint {
+    /// A spot off the grid, in line with a row or a column, looking inwards.
+    /// Trees are seen the same way as in part 1: only if every tree between
+    /// them and the viewer is shorter.
+    Outside { r: isize, c: isize },
+    /// A treehouse on a tree looking along the four directions, each one
+    /// stops at the first tree as tall as the treehouse tree (part 2).
+    Tree { r: usize, c: usize },
 }
 
-fn process(lines: &[String]) -> (usize, usize) {
-    let mut grid: Vec<Vec<u8>> = vec![];
-
-    let mut r = 0_usize;
-    for line in lines {
-        grid.push(vec![]);
+/// Per-tree results of the four directional sweeps
+struct ForestView {
+    visible: Vec<Vec<bool>>,
+    scenic: Vec<Vec<usize>>,
+}
 
-        for cn in line.chars() {
-            let n = cn as u8 - b'0';
+pub fn run(input: Lines<impl BufRead>) {
+    let strs = common::parse(input);
+    let grid = process(&strs);
+    let view = compute(&grid);
 
-            grid[r].push(n);
-        }
+    let vcnt = view.visible.iter().flatten().filter(|b| **b).count();
+    let max_sc = view.scenic.iter().flatten().copied().max().unwrap_or(0);
 
-        r += 1;
+    println!("count = {}", vcnt);
+    println!("max scenic = {}", max_sc);
+    println!("{}", heatmap(&view.scenic));
+
+    for (i, ((r, c), sc)) in top_scenic(&view.scenic, 3).into_iter().enumerate() {
+        let seen = visible_from(&grid, &Viewpoint::Tree { r, c }).len();
+        println!(
+            "#{} scenic spot ({}, {}) = {} | sees {} trees",
+            i + 1,
+            r,
+            c,
+            sc,
+            seen
+        );
     }
 
-    let len = grid.len();
-    let cols = grid[0].len();
-    let all_rows = 0..len;
-    let all_cols = 0..cols;
-
-    let mut vis: Vec<Vec<bool>> = vec![vec![false; cols]; len];
+    let west = visible_from(&grid, &Viewpoint::Outside { r: 0, c: -1 });
+    println!("seen from west of row 0: {:?}", west);
+}
 
-    for row in all_rows.clone() {
-        let mut prev = 99_u8;
-        all_cols
-            .clone()
-            .for_each(|col| is_visible(&mut vis, &grid, &mut prev, row, col));
+fn process(lines: &[String]) -> Vec<Vec<u8>> {
+    lines
+        .iter()
+        .map(|line| line.bytes().map(|b| b - b'0').collect())
+        .collect()
+}
 
-        let mut prev = 99_u8;
-        all_cols
-            .clone()
-            .rev()
-            .for_each(|col| is_visible(&mut vis, &grid, &mut prev, row, col));
+/// Runs a monotonic stack sweep from each of the four edges, every tree is
+/// pushed and popped at most once per sweep so the whole thing is linear in
+/// the number of trees.
+fn compute(grid: &[Vec<u8>]) -> ForestView {
+    let rows = grid.len();
+    let cols = grid.first().map_or(0, |r| r.len());
+
+    let mut view = ForestView {
+        visible: vec![vec![false; cols]; rows],
+        scenic: vec![vec![1; cols]; rows],
+    };
+
+    for r in 0..rows {
+        let line: Vec<(usize, usize)> = (0..cols).map(|c| (r, c)).collect();
+        sweep(grid, &line, &mut view);
+        sweep(grid, &line.into_iter().rev().collect::<Vec<_>>(), &mut view);
     }
 
-    for col in all_cols.clone() {
-        let mut prev = 99_u8;
-        all_rows
-            .clone()
-            .for_each(|row| is_visible(&mut vis, &grid, &mut prev, row, col));
-
-        let mut prev = 99_u8;
-        all_rows
-            .clone()
-            .rev()
-            .for_each(|row| is_visible(&mut vis, &grid, &mut prev, row, col));
+    for c in 0..cols {
+        let line: Vec<(usize, usize)> = (0..rows).map(|r| (r, c)).collect();
+        sweep(grid, &line, &mut view);
+        sweep(grid, &line.into_iter().rev().collect::<Vec<_>>(), &mut view);
     }
 
-    let mut max_sc = 0;
-    for r in all_rows.clone() {
-        for c in all_cols.clone() {
-            let sc = get_view_dist(&grid, grid[r][c], r, c);
-            max_sc = max_sc.max(sc);
-        }
-    }
+    view
+}
 
+/// Walks `line` (grid coords ordered from the edge inwards) looking back
+/// towards the edge.
+///
+/// The stack keeps the positions of trees not yet hidden by a later tree,
+/// so its heights never increase from bottom to top. After popping every
+/// tree lower than the current one, the top is the first tree blocking the
+/// view, and an empty stack means the edge can be seen.
+fn sweep(grid: &[Vec<u8>], line: &[(usize, usize)], view: &mut ForestView) {
+    let mut stack: Vec<usize> = vec![];
+
+    for (i, &(r, c)) in line.iter().enumerate() {
+        let cur = grid[r][c];
+        while let Some(&top) = stack.last() {
+            let (tr, tc) = line[top];
+            if grid[tr][tc] >= cur {
+                break;
+            }
+            stack.pop();
+        }
 
-    (vis.into_iter().flatten().filter(|b| *b).count(), max_sc)
-}
+        let dist = match stack.last() {
+            Some(&top) => i - top,
+            None => {
+                view.visible[r][c] = true;
+                i
+            }
+        };
+        view.scenic[r][c] *= dist;
 
-fn is_visible(vis: &mut [Vec<bool>], grid: &[Vec<u8>], prev: &mut u8, r: usize, c: usize) {
-    if *prev == 99 || *prev < grid[r][c] {
-        vis[r][c] = true;
-        *prev = grid[r][c];
+        stack.push(i);
     }
 }
 
-fn get_view_dist(grid: &[Vec<u8>], cur: u8, r: usize, c: usize) -> usize {
-    let rows = grid.len() as i32;
-    let cols = grid[0].len()  as i32;
-    
-    
-    
-    let mut ri: i32 = (r as i32) + 1;
-    let mut c1 = 0_usize;
-    while ri < rows {
-        c1 += 1;
-        if grid[ri as usize][c] >= cur {
-            break;
-        }
-        ri += 1;
-    }
+/// Draws the scenic scores on a log scale, one char per tree
+fn heatmap(scenic: &[Vec<usize>]) -> String {
+    let max = scenic.iter().flatten().copied().max().unwrap_or(0);
+    let top = (max as f64 + 1.0).ln().max(f64::MIN_POSITIVE);
+    let shades = (HEAT_RAMP.len() - 1) as f64;
+
+    scenic
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|sc| {
+                    let lvl = ((*sc as f64 + 1.0).ln() / top * shades).round() as usize;
+                    HEAT_RAMP[lvl] as char
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
 
-    ri = (r as i32) - 1;
-    let mut c2 = 0_usize;
-    while ri >= 0 {
-        c2 += 1;
-        if grid[ri as usize][c] >= cur {
-            break;
-        }
-        ri -= 1;
-    }
+/// The `k` best scenic spots as `((row, col), score)`, best first
+fn top_scenic(scenic: &[Vec<usize>], k: usize) -> Vec<((usize, usize), usize)> {
+    let mut spots: Vec<((usize, usize), usize)> = scenic
+        .iter()
+        .enumerate()
+        .flat_map(|(r, row)| row.iter().enumerate().map(move |(c, sc)| ((r, c), *sc)))
+        .collect();
+    spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    spots.truncate(k);
+
+    spots
+}
 
-    let mut ci = (c  as i32) - 1;
-    let mut c3 = 0_usize;
-    while ci >= 0 {
-        c3 += 1;
-        if grid[r][ci as usize] >= cur {
-            break;
+/// Coords of every tree seen from `vp`, nearest first for each direction.
+///
+/// An outside viewpoint that isn't in line with a row or column sees nothing.
+fn visible_from(grid: &[Vec<u8>], vp: &Viewpoint) -> Vec<(usize, usize)> {
+    let rows = grid.len() as isize;
+    let cols = grid.first().map_or(0, |r| r.len()) as isize;
+    let in_grid = |r: isize, c: isize| r >= 0 && r < rows && c >= 0 && c < cols;
+    let mut seen = vec![];
+
+    match *vp {
+        Viewpoint::Outside { r, c } => {
+            let dir = if (0..rows).contains(&r) && c < 0 {
+                DIRS[1]
+            } else if (0..rows).contains(&r) && c >= cols {
+                DIRS[3]
+            } else if (0..cols).contains(&c) && r < 0 {
+                DIRS[2]
+            } else if (0..cols).contains(&c) && r >= rows {
+                DIRS[0]
+            } else {
+                return seen;
+            };
+
+            // step onto the grid edge first
+            let (mut r, mut c) = (r.clamp(0, rows - 1), c.clamp(0, cols - 1));
+            let mut tallest: Option<u8> = None;
+            while in_grid(r, c) {
+                let h = grid[r as usize][c as usize];
+                if tallest.is_none_or(|t| h > t) {
+                    seen.push((r as usize, c as usize));
+                    tallest = Some(h);
+                }
+                r += dir.0;
+                c += dir.1;
+            }
         }
-        ci -= 1;
-    }
 
-    ci = (c as i32) + 1;
-    let mut c4 = 0_usize;
-    while ci < cols {
-        c4 += 1;
-        if grid[r][ci as usize] >= cur {
-            break;
+        Viewpoint::Tree { r, c } => {
+            let cur = grid[r][c];
+            for (dr, dc) in DIRS {
+                let (mut tr, mut tc) = (r as isize + dr, c as isize + dc);
+                while in_grid(tr, tc) {
+                    seen.push((tr as usize, tc as usize));
+                    if grid[tr as usize][tc as usize] >= cur {
+                        break;
+                    }
+                    tr += dr;
+                    tc += dc;
+                }
+            }
         }
-        ci += 1;
     }
 
-    c1 * c2 * c3 * c4
+    seen
 }
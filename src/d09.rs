@@ -1,134 +1,265 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashSet},
+    fmt::Display,
     io::{BufRead, Lines},
 };
 
 use crate::common;
 
+type Pos = (i32, i32);
+
+/// `cnt` single steps of (`dx`, `dy`), each of them in `-1..=1`
 #[derive(Clone)]
 struct Movement {
     dx: i32,
     dy: i32,
+    cnt: i32,
 }
 
-pub fn run(input: Lines<impl BufRead>) {
-    let lines = common::parse(input);
+struct RopeConfig {
+    /// Number of knots, including the head
+    knots: usize,
+    /// Max distance (on either axis) a knot can be from the one before it
+    /// without being pulled
+    slack: u32,
+}
 
-    let mvs = process(&lines);
+/// Print the rope map after every movement
+const PRINT_STEPS: bool = false;
 
-    let p1 = compute(&mvs, 2);
+const START: Pos = (0, 0);
 
-    println!("p1 = {}", p1);
+/// Final state of a simulated rope
+struct RopeTrail {
+    /// Knot positions at the end, head first
+    knots: Vec<Pos>,
+    /// Positions visited by every knot, head first
+    visited: Vec<HashSet<Pos>>,
+}
 
-    let p2 = compute(&mvs, 10);
+/// Reason a movement line can't be parsed, `line` is 1-based
+enum ParseError {
+    BadLine { line: usize },
+    BadCount { line: usize },
+    UnknownDir { line: usize, dir: String },
+}
 
-    println!("p2 = {}", p2);
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::BadLine { line } => write!(f, "line {}: expected `<dir> <count>`", line),
+            ParseError::BadCount { line } => {
+                write!(f, "line {}: count must be a non-negative number", line)
+            }
+            ParseError::UnknownDir { line, dir } => {
+                write!(f, "line {}: unknown direction `{}`", line, dir)
+            }
+        }
+    }
 }
 
-fn compute(mvs: &[Movement], segment_cnt: usize) -> usize {
-    let mut segments = vec![(0_i32, 0_i32); segment_cnt];
+/// Inclusive `(min, max)` corners of a map
+type Bounds = (Pos, Pos);
+
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
+    let lines = common::parse(input);
+
+    let mvs = match process(&lines) {
+        Ok(mvs) => mvs,
+        Err(e) => {
+            println!("invalid input: {}", e);
+            return;
+        }
+    };
 
-    let mut tail_pos_history: HashSet<(i32, i32)> = HashSet::new();
+    let p1 = compute(&mvs, &RopeConfig { knots: 2, slack: 1 });
 
-    tail_pos_history.insert(segments[segment_cnt - 1].clone());
+    println!("p1 = {}", p1.visited.last().unwrap().len());
+
+    let p2 = compute(
+        &mvs,
+        &RopeConfig {
+            knots: 10,
+            slack: 1,
+        },
+    );
+
+    println!("p2 = {}", p2.visited.last().unwrap().len());
+    let per_knot: Vec<usize> = p2.visited.iter().map(|v| v.len()).collect();
+    println!("p2 visited per knot = {:?}", per_knot);
+
+    let loose = compute(
+        &mvs,
+        &RopeConfig {
+            knots: 10,
+            slack: 2,
+        },
+    );
+
+    println!("p2 (slack 2) = {}", loose.visited.last().unwrap().len());
+
+    match args.first().map(|a| a.as_str()) {
+        Some("csv") => print!("{}", to_csv(&p2.visited)),
+        Some("json") => println!("{}", to_json(&p2.visited)),
+        Some("map") => {
+            let bounds = bounds_of(p2.visited.iter().flatten());
+            println!("{}\n", render_rope(&p2.knots, bounds));
+            println!("{}", render_visited(p2.visited.last().unwrap(), bounds));
+        }
+        _ => {}
+    }
+}
+
+/// Simulates the rope, keeping the positions visited by every knot
+fn compute(mvs: &[Movement], cfg: &RopeConfig) -> RopeTrail {
+    let mut segments: Vec<Pos> = vec![START; cfg.knots];
+    let mut history: Vec<HashSet<Pos>> = segments.iter().map(|p| HashSet::from([*p])).collect();
 
     // simulates movement
     for mv in mvs {
-        move_segment(&mut segments, &mut tail_pos_history, mv);
+        move_segment(&mut segments, &mut history, mv, cfg.slack);
+
+        if PRINT_STEPS {
+            let bounds = bounds_of(segments.iter().chain([START].iter()));
+            println!("{}\n", render_rope(&segments, bounds));
+        }
     }
 
-    tail_pos_history.len()
+    RopeTrail {
+        knots: segments,
+        visited: history,
+    }
 }
 
-fn move_segment(
-    segments: &mut [(i32, i32)],
-    tail_history: &mut HashSet<(i32, i32)>,
-    fmv: &Movement,
-) {
-    let mv = (*fmv).clone();
+fn move_segment(segments: &mut [Pos], history: &mut [HashSet<Pos>], mv: &Movement, slack: u32) {
+    for _ in 0..mv.cnt {
+        segments[0].0 += mv.dx;
+        segments[0].1 += mv.dy;
+        history[0].insert(segments[0]);
 
-    let len = segments.len();
+        for i in 1..segments.len() {
+            let head = segments[i - 1];
+            let mut tail = segments[i];
 
-    let horizontal = fmv.dx != 0;
-    let cnt = if horizontal { mv.dx.abs() } else { mv.dy.abs() };
-    let inc = if horizontal { mv.dx / cnt } else { mv.dy / cnt };
+            // step towards the head (straight or diagonal) until back in range
+            loop {
+                let delta = (head.0 - tail.0, head.1 - tail.1);
+                if delta.0.unsigned_abs().max(delta.1.unsigned_abs()) <= slack {
+                    break;
+                }
+                tail.0 += delta.0.signum();
+                tail.1 += delta.1.signum();
+                history[i].insert(tail);
+            }
 
-    for _ in 0..cnt {
-        let mut rhead = &mut segments[0];
-        if horizontal {
-            rhead.0 += inc;
-        } else {
-            rhead.1 += inc;
+            segments[i] = tail;
         }
+    }
+}
 
-        for i in 1..len {
-            let head = segments[i - 1];
-            let tail = segments[i];
-            let mut ntail = tail.clone();
-
-            let delta = (head.0 - tail.0, head.1 - tail.1);
-
-            if delta.0.abs() >= 2 && delta.1.abs() >= 2 {
-                // Diagonal movement
-                ntail.0 = if tail.0 < head.0 {
-                    head.0 - 1
-                } else {
-                    head.0 + 1
-                };
-                ntail.1 = if tail.1 < head.1 {
-                    head.1 - 1
-                } else {
-                    head.1 + 1
-                };
-            } else if delta.0.abs() >= 2 {
-                // Horizontal movement
-                ntail.1 = head.1;
-                ntail.0 = if tail.0 < head.0 {
-                    head.0 - 1
-                } else {
-                    head.0 + 1
-                };
-            } else if delta.1.abs() >= 2 {
-                // Vertical movement
-                ntail.0 = head.0;
-                ntail.1 = if tail.1 < head.1 {
-                    head.1 - 1
-                } else {
-                    head.1 + 1
-                };
+fn process(lines: &[String]) -> Result<Vec<Movement>, ParseError> {
+    let mut mvs = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let line_no = i + 1;
+        let Some((dir, cnt)) = line.split_once(' ') else {
+            return Err(ParseError::BadLine { line: line_no });
+        };
+        let cnt = cnt
+            .parse::<i32>()
+            .ok()
+            .filter(|c| *c >= 0)
+            .ok_or(ParseError::BadCount { line: line_no })?;
+        let (dx, dy) = match dir {
+            "U" => (0, 1),
+            "D" => (0, -1),
+            "L" => (-1, 0),
+            "R" => (1, 0),
+            "UL" => (-1, 1),
+            "UR" => (1, 1),
+            "DL" => (-1, -1),
+            "DR" => (1, -1),
+            _ => {
+                return Err(ParseError::UnknownDir {
+                    line: line_no,
+                    dir: dir.to_string(),
+                })
             }
-            segments[i] = ntail;
-        }
+        };
 
-        let real_tail = segments.last().unwrap();
-        tail_history.insert(real_tail.clone());
+        mvs.push(Movement { dx, dy, cnt });
     }
+
+    Ok(mvs)
 }
 
-fn process(lines: &[String]) -> Vec<Movement> {
-    let mut mvs = vec![];
-    for line in lines {
-        let spl: Vec<&str> = line.split(" ").collect();
-        let dp = spl[1].parse::<i32>().unwrap();
-        let mult = if spl[0] == "U" || spl[0] == "R" {
-            1
-        } else {
-            -1
-        };
-        let vertical = spl[0] == "U" || spl[0] == "D";
+fn bounds_of<'a>(ps: impl Iterator<Item = &'a Pos>) -> Bounds {
+    ps.fold((START, START), |((x0, y0), (x1, y1)), (x, y)| {
+        ((x0.min(*x), y0.min(*y)), (x1.max(*x), y1.max(*y)))
+    })
+}
+
+/// Draws a map the way the puzzle does: top row is the highest `y`
+fn render_map(bounds: Bounds, cell: impl Fn(Pos) -> char) -> String {
+    let ((x0, y0), (x1, y1)) = bounds;
+    (y0..=y1)
+        .rev()
+        .map(|y| (x0..=x1).map(|x| cell((x, y))).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Knots are drawn as `H`, then `1`..`9` (`T` for a two knot rope),
+/// a front knot hides the ones behind it, `s` marks the start
+fn render_rope(knots: &[Pos], bounds: Bounds) -> String {
+    render_map(bounds, |p| match knots.iter().position(|k| *k == p) {
+        Some(0) => 'H',
+        Some(_) if knots.len() == 2 => 'T',
+        Some(i) => std::char::from_digit(i as u32, 36).unwrap_or('+'),
+        None if p == START => 's',
+        None => '.',
+    })
+}
 
-        if vertical {
-            mvs.push(Movement {
-                dx: 0,
-                dy: dp * mult,
-            });
+/// Visited positions are drawn as `#`, `s` marks the start
+fn render_visited(visited: &HashSet<Pos>, bounds: Bounds) -> String {
+    render_map(bounds, |p| {
+        if p == START {
+            's'
+        } else if visited.contains(&p) {
+            '#'
         } else {
-            mvs.push(Movement {
-                dx: dp * mult,
-                dy: 0,
-            });
+            '.'
+        }
+    })
+}
+
+/// One `knot,x,y` row per visited position, sorted
+fn to_csv(visited: &[HashSet<Pos>]) -> String {
+    let mut out = String::from("knot,x,y\n");
+    for (k, ps) in visited.iter().enumerate() {
+        for (x, y) in ps.iter().collect::<BTreeSet<_>>() {
+            out += &format!("{},{},{}\n", k, x, y);
         }
     }
 
-    mvs
+    out
+}
+
+/// `[{"knot": k, "visited": [[x, y], ...]}, ...]` with sorted positions
+fn to_json(visited: &[HashSet<Pos>]) -> String {
+    let knots: Vec<String> = visited
+        .iter()
+        .enumerate()
+        .map(|(k, ps)| {
+            let ps: Vec<String> = ps
+                .iter()
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|(x, y)| format!("[{},{}]", x, y))
+                .collect();
+            format!("{{\"knot\":{},\"visited\":[{}]}}", k, ps.join(","))
+        })
+        .collect();
+
+    format!("[{}]", knots.join(","))
 }
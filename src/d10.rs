@@ -1,4 +1,8 @@
-use std::io::{BufRead, Lines};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{BufRead, Lines},
+};
 
 use crate::common;
 
@@ -6,20 +10,397 @@ const SCREEN_W: usize = 40;
 const SCREEN_H: usize = 6;
 const SCREEN_DIM: usize = SCREEN_W * SCREEN_H;
 const CYCLE_INCR: usize = 40;
+const FIRST_CHECKPOINT: usize = 20;
 
-enum OpCode {
-    Noop,
-    Skip,
-    FetchAddX(i32),
+/// Width of a letter cell on the screen, 4 pixels plus a blank column
+const GLYPH_W: usize = 5;
+
+/// AoC 4x6 font, each glyph is its 6 rows of 4 pixels joined together
+const FONT: [(char, &str); 17] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+/// Print every cycle while the program runs
+const TRACE: bool = false;
+
+/// A sample program using the extended instruction set
+const DEMO_PROG: &str = "\
+; x = x * 3 + 2 forever, y counts the loops
+start:
+    mul 3
+    addx 2
+    addy 1
+    jmp start";
+
+/// Program registers, X drives the sprite
+#[derive(Clone, Copy)]
+struct Regs {
+    x: i32,
+    y: i32,
+}
+
+/// What an instruction takes after its mnemonic
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    None,
+    /// A number
+    Imm,
+    /// A label, resolved to an instruction index by the assembler
+    Label,
+}
+
+/// Static info of an opcode
+struct OpSpec {
+    name: &'static str,
+    /// Cycles the instruction takes, its effect lands after the last one
+    cycles: usize,
+    operand: Operand,
+    /// Applies the instruction, returning the jump target if it branches
+    exec: fn(&mut Regs, i32) -> Option<usize>,
+}
+
+/// Instruction set table, new instructions only need an entry here
+const INSTR_SET: [OpSpec; 5] = [
+    OpSpec {
+        name: "noop",
+        cycles: 1,
+        operand: Operand::None,
+        exec: |_, _| None,
+    },
+    OpSpec {
+        name: "addx",
+        cycles: 2,
+        operand: Operand::Imm,
+        exec: |r, v| {
+            r.x += v;
+            None
+        },
+    },
+    OpSpec {
+        name: "addy",
+        cycles: 2,
+        operand: Operand::Imm,
+        exec: |r, v| {
+            r.y += v;
+            None
+        },
+    },
+    OpSpec {
+        name: "mul",
+        cycles: 3,
+        operand: Operand::Imm,
+        exec: |r, v| {
+            r.x = r.x.wrapping_mul(v);
+            None
+        },
+    },
+    OpSpec {
+        name: "jmp",
+        cycles: 1,
+        operand: Operand::Label,
+        exec: |_, target| Some(target as usize),
+    },
+];
+
+/// An opcode (index into `INSTR_SET`) with its operand, `0` when unused
+#[derive(Clone, Copy)]
+struct Instr {
+    op: usize,
+    arg: i32,
+}
+
+impl Instr {
+    fn spec(&self) -> &'static OpSpec {
+        &INSTR_SET[self.op]
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let spec = self.spec();
+        match spec.operand {
+            Operand::None => write!(f, "{}", spec.name),
+            Operand::Imm => write!(f, "{} {}", spec.name, self.arg),
+            // `disassemble` names the jump targets after their pc
+            Operand::Label => write!(f, "{} l{}", spec.name, self.arg),
+        }
+    }
+}
+
+/// Reason a source line can't be assembled, `line` is 1-based
+enum AsmError {
+    UnknownOp { line: usize, name: String },
+    BadOperand { line: usize },
+    UnknownLabel { line: usize, name: String },
+    DuplicateLabel { line: usize, name: String },
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmError::UnknownOp { line, name } => {
+                write!(f, "line {}: unknown instruction `{}`", line, name)
+            }
+            AsmError::BadOperand { line } => write!(f, "line {}: invalid operand", line),
+            AsmError::UnknownLabel { line, name } => {
+                write!(f, "line {}: unknown label `{}`", line, name)
+            }
+            AsmError::DuplicateLabel { line, name } => {
+                write!(f, "line {}: label `{}` already defined", line, name)
+            }
+        }
+    }
 }
 
-pub fn run(input: Lines<impl BufRead>) {
+#[derive(Clone, Copy, Debug)]
+enum Breakpoint {
+    /// Stops once the given (1-based) cycle has run
+    Cycle(usize),
+    /// Stops once an instruction sets X to the given value
+    RegX(i32),
+}
+
+impl Breakpoint {
+    /// Reads `c<cycle>` / `x<value>` specs, none at all gives a break at the
+    /// first signal checkpoint and whenever X turns -1
+    fn from_args(args: &[String]) -> Result<Vec<Breakpoint>, String> {
+        if args.is_empty() {
+            return Ok(vec![
+                Breakpoint::Cycle(FIRST_CHECKPOINT),
+                Breakpoint::RegX(-1),
+            ]);
+        }
+
+        args.iter()
+            .map(|a| {
+                let bp = match a.split_at_checked(1) {
+                    Some(("c", n)) => n.parse().ok().map(Breakpoint::Cycle),
+                    Some(("x", n)) => n.parse().ok().map(Breakpoint::RegX),
+                    _ => None,
+                };
+                bp.ok_or_else(|| format!("`{}` is not a `c<cycle>` or `x<value>` breakpoint", a))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum StopReason {
+    Halted,
+    CycleLimit,
+    Break(Breakpoint),
+}
+
+/// Cycle-accurate model of the handheld CPU.
+///
+/// `x_hist[c - 1]` is the value of X during cycle `c`.
+struct Cpu {
+    regs: Regs,
+    pc: usize,
+    /// Cycles already spent on the instruction at `pc`
+    busy: usize,
+    x_hist: Vec<i32>,
+    trace: bool,
+    breakpoints: Vec<Breakpoint>,
+    /// Stops the run after this many cycles, for programs that loop forever
+    cycle_limit: Option<usize>,
+}
+
+impl Cpu {
+    fn new() -> Self {
+        Cpu {
+            regs: Regs { x: 1, y: 0 },
+            pc: 0,
+            busy: 0,
+            x_hist: vec![],
+            trace: TRACE,
+            breakpoints: vec![],
+            cycle_limit: None,
+        }
+    }
+
+    fn cycle(&self) -> usize {
+        self.x_hist.len()
+    }
+
+    /// Value of X during the given (1-based) cycle, if it has run already
+    fn x_at(&self, cycle: usize) -> Option<i32> {
+        cycle
+            .checked_sub(1)
+            .and_then(|c| self.x_hist.get(c))
+            .copied()
+    }
+
+    /// Runs one cycle, `None` once the program is done
+    fn step(&mut self, prog: &[Instr]) -> Option<()> {
+        let instr = prog.get(self.pc)?;
+
+        self.x_hist.push(self.regs.x);
+        self.busy += 1;
+        if self.trace {
+            println!(
+                "cycle={:03} | x={:3} | {} ({}/{})",
+                self.cycle(),
+                self.regs.x,
+                instr,
+                self.busy,
+                instr.spec().cycles
+            );
+        }
+
+        if self.busy == instr.spec().cycles {
+            let jump = (instr.spec().exec)(&mut self.regs, instr.arg);
+            self.pc = jump.unwrap_or(self.pc + 1);
+            self.busy = 0;
+        }
+
+        Some(())
+    }
+
+    /// Runs until the program ends or a breakpoint hits, calling `on_cycle`
+    /// with `(cycle, x)` for every cycle. Can be called again to resume.
+    fn run(&mut self, prog: &[Instr], mut on_cycle: impl FnMut(usize, i32)) -> StopReason {
+        loop {
+            if self.cycle_limit.is_some_and(|l| self.cycle() >= l) {
+                return StopReason::CycleLimit;
+            }
+
+            let x_before = self.regs.x;
+            if self.step(prog).is_none() {
+                return StopReason::Halted;
+            }
+            on_cycle(self.cycle(), self.x_at(self.cycle()).unwrap());
+
+            let hit = self.breakpoints.iter().find(|bp| match bp {
+                Breakpoint::Cycle(c) => *c == self.cycle(),
+                Breakpoint::RegX(v) => self.regs.x != x_before && self.regs.x == *v,
+            });
+            if let Some(bp) = hit {
+                return StopReason::Break(*bp);
+            }
+        }
+    }
+}
+
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
     let lines = common::parse(input);
-    let ops = process(&lines);
+    let prog = match assemble(&lines) {
+        Ok(prog) => prog,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut cpu = Cpu::new();
+    if args.first().map(|a| a.as_str()) == Some("break") {
+        match Breakpoint::from_args(&args[1..]) {
+            Ok(bps) => cpu.breakpoints = bps,
+            Err(e) => {
+                println!("invalid args: {}", e);
+                return;
+            }
+        }
+    }
+
     let mut pixels: [char; SCREEN_DIM] = ['.'; SCREEN_DIM];
-    let sum = compute(&ops, &mut pixels);
+    let mut sum = 0;
+    loop {
+        let stop = cpu.run(&prog, |cycle, x| {
+            draw_pixel(&mut pixels, cycle, x);
+            sum += signal_strength(cycle, x);
+        });
+
+        match stop {
+            StopReason::Halted | StopReason::CycleLimit => break,
+            StopReason::Break(bp) => println!(
+                "break {:?} at cycle {} | x={} | pc={}",
+                bp,
+                cpu.cycle(),
+                cpu.regs.x,
+                cpu.pc
+            ),
+        }
+    }
+
     println!("sum: {}", sum);
+    println!("x at cycle 220: {:?}", cpu.x_at(220));
     render(pixels);
+    println!("screen: {}", ocr(&pixels));
+
+    if args.first().map(|a| a.as_str()) != Some("demo") {
+        return;
+    }
+    let demo: Vec<String> = DEMO_PROG.lines().map(String::from).collect();
+    if let Ok(prog) = assemble(&demo) {
+        println!("{}", disassemble(&prog));
+
+        let mut cpu = Cpu::new();
+        cpu.cycle_limit = Some(CYCLE_INCR);
+        let stop = cpu.run(&prog, |_, _| {});
+        println!(
+            "demo {:?} at cycle {} | x={} | y={}",
+            stop,
+            cpu.cycle(),
+            cpu.regs.x,
+            cpu.regs.y
+        );
+    }
+}
+
+/// Reads the letters off the screen, unknown glyphs come out as `?`
+fn ocr(pixels: &[char; SCREEN_DIM]) -> String {
+    (0..SCREEN_W / GLYPH_W)
+        .map(|i| {
+            let glyph: String = (0..SCREEN_H)
+                .flat_map(|r| {
+                    let start = r * SCREEN_W + i * GLYPH_W;
+                    pixels[start..start + 4].iter()
+                })
+                .map(|p| if *p == '#' { '#' } else { '.' })
+                .collect();
+
+            FONT.iter()
+                .find(|(_, g)| *g == glyph)
+                .map_or('?', |(ch, _)| *ch)
+        })
+        .collect()
+}
+
+fn signal_strength(cycle: usize, x: i32) -> i32 {
+    if cycle >= FIRST_CHECKPOINT && (cycle - FIRST_CHECKPOINT).is_multiple_of(CYCLE_INCR) {
+        cycle as i32 * x
+    } else {
+        0
+    }
+}
+
+/// Lights the pixel drawn during `cycle` if the 3 pixel wide sprite,
+/// centered on X, covers it
+fn draw_pixel(pixels: &mut [char; SCREEN_DIM], cycle: usize, x: i32) {
+    let pos = cycle - 1;
+    if pos >= SCREEN_DIM {
+        return;
+    }
+
+    let col = (pos % SCREEN_W) as i32;
+    pixels[pos] = if (col - x).abs() <= 1 { '#' } else { '.' };
 }
 
 fn render(pixels: [char; SCREEN_DIM]) {
@@ -32,87 +413,89 @@ fn render(pixels: [char; SCREEN_DIM]) {
             };
             print!("{}", p);
         }
-        println!("");
+        println!();
     }
 }
 
-fn compute(ops: &[OpCode], pixels_out: &mut [char; SCREEN_DIM]) -> i32 {
-    let mut sum = 0;
-
-    let mut pc = 0;
-    let mut reg_x = 1_i32;
-    let mut checkpoint = 20;
-
-    let mut sprites: Vec<char> = "###....................................."
-        .chars()
-        .into_iter()
+/// Builds a program from its source.
+///
+/// One instruction per line, `name:` lines define labels, anything after a
+/// `;` is a comment.
+fn assemble(lines: &[String]) -> Result<Vec<Instr>, AsmError> {
+    let code: Vec<(usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split(';').next().unwrap().trim()))
+        .filter(|(_, l)| !l.is_empty())
         .collect();
 
-    for op in ops {
-        print!("pc={:03} | ", pc);
-        match op {
-            // During fetch counter is not incremented
-            OpCode::FetchAddX(num) => {
-                let sm = &mut sprites;
-                if reg_x - 1 >= 0 {
-                    sm[(reg_x - 1) as usize] = '.';
-                }
-                if reg_x >= 0 {
-                    sm[(reg_x + 0) as usize] = '.';
-                }
-                if ((reg_x + 1) as usize) < sm.len() {
-                    sm[(reg_x + 1) as usize] = '.';
-                }
-                reg_x += num;
-                if reg_x - 1 >= 0 {
-                    sm[(reg_x - 1) as usize] = '#';
+    // first pass, label -> index of the next instruction
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut cnt = 0;
+    for (line, l) in code.iter() {
+        match l.strip_suffix(':') {
+            Some(name) => {
+                if labels.insert(name, cnt).is_some() {
+                    return Err(AsmError::DuplicateLabel {
+                        line: *line,
+                        name: name.into(),
+                    });
                 }
-                if reg_x >= 0 {
-                    sm[(reg_x + 0) as usize] = '#';
-                }
-                if ((reg_x + 1) as usize) < sm.len() {
-                    sm[(reg_x + 1) as usize] = '#';
-                }
-
-                println!("reg_x={}", reg_x);
             }
+            None => cnt += 1,
+        }
+    }
 
-            OpCode::Noop | OpCode::Skip => {
-                let s = &sprites;
-                pixels_out[pc as usize] = s[(pc % SCREEN_W) as usize];
-                pc += 1;
+    let mut prog = vec![];
+    for (line, l) in code.into_iter().filter(|(_, l)| !l.ends_with(':')) {
+        let spl: Vec<&str> = l.split_whitespace().collect();
+        let op = INSTR_SET
+            .iter()
+            .position(|s| s.name == spl[0])
+            .ok_or(AsmError::UnknownOp {
+                line,
+                name: spl[0].into(),
+            })?;
 
-                println!("noop");
-            }
-        }
+        let arg = match (INSTR_SET[op].operand, &spl[1..]) {
+            (Operand::None, []) => 0,
+            (Operand::Imm, [v]) => v
+                .parse::<i32>()
+                .map_err(|_| AsmError::BadOperand { line })?,
+            (Operand::Label, [name]) => *labels.get(name).ok_or(AsmError::UnknownLabel {
+                line,
+                name: name.to_string(),
+            })? as i32,
+            _ => return Err(AsmError::BadOperand { line }),
+        };
 
-        if checkpoint == pc {
-            let sig_strength = (pc as i32) * reg_x;
-            checkpoint += CYCLE_INCR;
-            sum += sig_strength
-        }
+        prog.push(Instr { op, arg });
     }
 
-    sum
+    Ok(prog)
 }
 
-fn process(lines: &[String]) -> Vec<OpCode> {
-    let mut ops = vec![];
-    for line in lines {
-        let spl: Vec<&str> = line.split(" ").collect();
-        match spl[0] {
-            "noop" => {
-                ops.push(OpCode::Noop);
-            }
+/// Pretty-prints a program back to source that `assemble` accepts,
+/// jump targets get `l<index>` labels
+fn disassemble(prog: &[Instr]) -> String {
+    let targets: HashSet<i32> = prog
+        .iter()
+        .filter(|i| i.spec().operand == Operand::Label)
+        .map(|i| i.arg)
+        .collect();
 
-            "addx" => {
-                ops.push(OpCode::Skip);
-                ops.push(OpCode::Skip);
-                ops.push(OpCode::FetchAddX(spl[1].parse::<i32>().unwrap()));
-            }
-            _ => unimplemented!(),
+    let mut out = vec![];
+    for (pc, instr) in prog.iter().enumerate() {
+        if targets.contains(&(pc as i32)) {
+            out.push(format!("l{}:", pc));
         }
+
+        out.push(format!("    {:<12}; {:04}", instr.to_string(), pc));
+    }
+    // a jump past the last instruction
+    if targets.contains(&(prog.len() as i32)) {
+        out.push(format!("l{}:", prog.len()));
     }
 
-    ops
+    out.join("\n")
 }
@@ -1,232 +1,829 @@
-use std::io::{BufRead, Lines};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{BufRead, Lines},
+    iter::Peekable,
+    str::Chars,
+};
 
 use crate::common;
 
-enum OpSymbol {
-    OpMult,
-    OpAdd,
-    ValOld,
-    Val(u64),
+/// Widened so `old * old` can't overflow before the reduction kicks in
+type Worry = i128;
+
+const PART_1_ROUNDS: u64 = 20;
+const PART_2_ROUNDS: u64 = 10_000;
+const HUGE_ROUNDS: u64 = 1_000_000_000_000;
+/// Rounds an item is followed while looking for a cycle, past that the
+/// simulation gives up instead of playing every round
+const MAX_CYCLE_SEARCH: u64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
 }
 
-impl OpSymbol {
-    fn is_val_old(&self) -> bool {
-        if let OpSymbol::ValOld = self {
-            true
-        } else {
-            false
+impl BinOp {
+    /// `None` on overflow or division by zero
+    fn eval(&self, a: Worry, b: Worry) -> Option<Worry> {
+        match self {
+            BinOp::Add => a.checked_add(b),
+            BinOp::Subtract => a.checked_sub(b),
+            BinOp::Multiply => a.checked_mul(b),
+            BinOp::Divide => a.checked_div(b),
+            BinOp::Modulo => a.checked_rem_euclid(b),
         }
     }
 
-    fn get_value(&self) -> u64 {
-        if let OpSymbol::Val(num) = self {
-            *num
-        } else {
-            0
+    fn symbol(&self) -> char {
+        match self {
+            BinOp::Add => '+',
+            BinOp::Subtract => '-',
+            BinOp::Multiply => '*',
+            BinOp::Divide => '/',
+            BinOp::Modulo => '%',
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Add | BinOp::Subtract => 1,
+            BinOp::Multiply | BinOp::Divide | BinOp::Modulo => 2,
         }
     }
 }
 
-struct CMonkey<TF, THF>
-where
-    TF: Fn(u64) -> u64,
-    THF: Fn(u64) -> usize,
-{
-    inspect_cnt: usize,
-    items: Vec<u64>,
-    super_mod: u64,
-    transform_fn: TF,
-    throw_fn: THF,
+/// Worry level expression, the right hand side of `new = ...`
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Old,
+    Constant(Worry),
+    Negate(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
 }
 
-pub fn run(input: Lines<impl BufRead>) {
-    let lines = common::parse(input);
+impl Expr {
+    /// `None` on overflow or division by zero
+    fn eval(&self, old: Worry) -> Option<Worry> {
+        match self {
+            Expr::Old => Some(old),
+            Expr::Constant(v) => Some(*v),
+            Expr::Negate(e) => e.eval(old)?.checked_neg(),
+            Expr::Binary(op, a, b) => op.eval(a.eval(old)?, b.eval(old)?),
+        }
+    }
 
-    let mut p = process(&lines);
-    let bak_items: Vec<Vec<u64>> = p.iter().map(|m| m.items.clone()).collect();
+    /// Modulus `m` such that `eval(old) ≡ eval(old mod m) (mod m')` for any
+    /// multiple `m'` of it, `None` when there is no such thing (division, or
+    /// modulo by something else than a constant)
+    fn modulus(&self) -> Option<Worry> {
+        match self {
+            Expr::Old | Expr::Constant(_) => Some(1),
+            Expr::Negate(e) => e.modulus(),
+            Expr::Binary(BinOp::Divide, _, _) => None,
+            Expr::Binary(BinOp::Modulo, a, b) => match **b {
+                Expr::Constant(k) if k != 0 => Some(lcm(a.modulus()?, k.abs())),
+                _ => None,
+            },
+            Expr::Binary(_, a, b) => Some(lcm(a.modulus()?, b.modulus()?)),
+        }
+    }
 
-    let lb = compute(&mut p, true, 20);
-    let lblen = lb.len();
-    println!(
-        "two most active: {} * {} = {}",
-        lb[lblen - 1],
-        lb[lblen - 2],
-        lb[lblen - 1] * lb[lblen - 2]
-    );
+    /// Binding strength when printed, atoms never need parentheses
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            _ => 3,
+        }
+    }
+}
 
-    for m in 0..p.len() {
-        p[m].items = bak_items[m].clone();
-        p[m].inspect_cnt = 0;
+/// Check deciding where an item is thrown
+#[derive(Clone, Debug, PartialEq)]
+enum Test {
+    DivisibleBy(Worry),
+    NotDivisibleBy(Worry),
+    GreaterThan(Worry),
+    LessThan(Worry),
+    EqualTo(Worry),
+}
+
+impl Test {
+    fn check(&self, wl: Worry) -> bool {
+        match self {
+            Test::DivisibleBy(n) => wl % n == 0,
+            Test::NotDivisibleBy(n) => wl % n != 0,
+            Test::GreaterThan(n) => wl > *n,
+            Test::LessThan(n) => wl < *n,
+            Test::EqualTo(n) => wl == *n,
+        }
     }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Monkey {
+    id: usize,
+    items: Vec<Worry>,
+    op: Expr,
+    test: Test,
+    if_true: usize,
+    if_false: usize,
+}
+
+/// Prints with as few parentheses as `parse_expr` needs to read it back
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Old => write!(f, "old"),
+            Expr::Constant(v) => write!(f, "{}", v),
+            Expr::Negate(e) if e.precedence() < 3 => write!(f, "-({})", e),
+            Expr::Negate(e) => write!(f, "-{}", e),
+            Expr::Binary(op, a, b) => {
+                // operators are left associative
+                if a.precedence() < op.precedence() {
+                    write!(f, "({})", a)?;
+                } else {
+                    write!(f, "{}", a)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if b.precedence() <= op.precedence() {
+                    write!(f, "({})", b)
+                } else {
+                    write!(f, "{}", b)
+                }
+            }
+        }
+    }
+}
+
+impl Display for Test {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Test::DivisibleBy(n) => write!(f, "divisible by {}", n),
+            Test::NotDivisibleBy(n) => write!(f, "not divisible by {}", n),
+            Test::GreaterThan(n) => write!(f, "greater than {}", n),
+            Test::LessThan(n) => write!(f, "less than {}", n),
+            Test::EqualTo(n) => write!(f, "equal to {}", n),
+        }
+    }
+}
+
+/// Prints the monkey the same way the puzzle input does
+impl Display for Monkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items: Vec<String> = self.items.iter().map(|w| w.to_string()).collect();
+        writeln!(f, "Monkey {}:", self.id)?;
+        writeln!(f, "  Starting items: {}", items.join(", "))?;
+        writeln!(f, "  Operation: new = {}", self.op)?;
+        writeln!(f, "  Test: {}", self.test)?;
+        writeln!(f, "    If true: throw to monkey {}", self.if_true)?;
+        write!(f, "    If false: throw to monkey {}", self.if_false)
+    }
+}
+
+/// Reason a monkey definition can't be parsed.
+///
+/// `line` is 1-based, `col` is the 0-based char offset inside the expression.
+#[derive(Debug)]
+enum ParseError {
+    Expr {
+        line: usize,
+        col: usize,
+    },
+    Test {
+        line: usize,
+    },
+    Number {
+        line: usize,
+    },
+    /// A field line before any `Monkey N:` header
+    Orphan {
+        line: usize,
+    },
+    UnknownField {
+        line: usize,
+    },
+    DuplicateField {
+        line: usize,
+    },
+    /// `line` is the monkey header
+    MissingField {
+        line: usize,
+        field: &'static str,
+    },
+    /// Monkey ids must be `0..n`, each used once
+    MonkeyId {
+        line: usize,
+    },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Expr { line, col } => {
+                write!(f, "line {}: bad expression at char {}", line, col)
+            }
+            ParseError::Test { line } => write!(f, "line {}: unknown test", line),
+            ParseError::Number { line } => write!(f, "line {}: expected a number", line),
+            ParseError::Orphan { line } => write!(f, "line {}: field outside of a monkey", line),
+            ParseError::UnknownField { line } => write!(f, "line {}: unknown field", line),
+            ParseError::DuplicateField { line } => {
+                write!(f, "line {}: field given more than once", line)
+            }
+            ParseError::MissingField { line, field } => {
+                write!(f, "line {}: monkey has no `{}`", line, field)
+            }
+            ParseError::MonkeyId { line } => {
+                write!(f, "line {}: monkey id is duplicated or out of range", line)
+            }
+        }
+    }
+}
 
-    let lb = compute(&mut p, false, 10000);
-    let lblen = lb.len();
+/// How worry levels are kept in check after each inspection
+#[derive(Clone, Copy, Debug)]
+enum Relief {
+    DivideBy(Worry),
+    Modulo(Worry),
+    None,
+}
+
+/// Round by round record of where every item went.
+///
+/// `paths[item][round]` lists the monkeys that inspected the item during
+/// that round, followed by the monkey holding it once the round is over.
+/// Items are numbered by starting monkey, then by position in its list.
+struct Trace {
+    monkeys: usize,
+    paths: Vec<Vec<Vec<usize>>>,
+}
+
+impl Trace {
+    /// `throws[from][to]` over the whole run
+    fn throws(&self) -> Vec<Vec<u64>> {
+        let mut throws = vec![vec![0; self.monkeys]; self.monkeys];
+        for round in self.paths.iter().flatten() {
+            for w in round.windows(2) {
+                throws[w[0]][w[1]] += 1;
+            }
+        }
+
+        throws
+    }
+
+    /// `held[round][monkey]`, items held at the start of each round, plus
+    /// one last entry for the end of the run
+    fn held(&self) -> Vec<Vec<usize>> {
+        let rounds = self.paths.first().map_or(0, |p| p.len());
+        let mut held = vec![vec![0; self.monkeys]; rounds + 1];
+        for item in self.paths.iter() {
+            for (r, round) in item.iter().enumerate() {
+                held[r][round[0]] += 1;
+            }
+            if let Some(last) = item.last().and_then(|round| round.last()) {
+                held[rounds][*last] += 1;
+            }
+        }
+
+        held
+    }
+
+    fn paths_csv(&self) -> String {
+        let mut out = String::from("item,round,path\n");
+        for (i, item) in self.paths.iter().enumerate() {
+            for (r, round) in item.iter().enumerate() {
+                let path: Vec<String> = round.iter().map(|m| m.to_string()).collect();
+                out += &format!("{},{},{}\n", i, r + 1, path.join(">"));
+            }
+        }
+
+        out
+    }
+
+    fn throws_csv(&self) -> String {
+        let mut out = String::from("from,to,throws\n");
+        for (from, row) in self.throws().iter().enumerate() {
+            for (to, cnt) in row.iter().enumerate().filter(|(_, c)| **c > 0) {
+                out += &format!("{},{},{}\n", from, to, cnt);
+            }
+        }
+
+        out
+    }
+
+    /// Round 0 is the starting position
+    fn held_csv(&self) -> String {
+        let mut out = String::from("round,monkey,items\n");
+        for (r, row) in self.held().iter().enumerate() {
+            for (m, cnt) in row.iter().enumerate() {
+                out += &format!("{},{},{}\n", r, m, cnt);
+            }
+        }
+
+        out
+    }
+}
+
+/// Reason a simulation can't go on
+#[derive(Debug)]
+enum SimError {
+    Overflow { monkey: usize, worry: Worry },
+    NoSuchMonkey { monkey: usize, target: usize },
+    NoCycle { rounds: u64 },
+}
+
+impl Display for SimError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimError::Overflow { monkey, worry } => write!(
+                f,
+                "monkey {}: worry level overflows when inspecting {}",
+                monkey, worry
+            ),
+            SimError::NoSuchMonkey { monkey, target } => {
+                write!(f, "monkey {}: throws to unknown monkey {}", monkey, target)
+            }
+            SimError::NoCycle { rounds } => {
+                write!(
+                    f,
+                    "an item did not repeat its state within {} rounds",
+                    rounds
+                )
+            }
+        }
+    }
+}
+
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
+    let lines = common::parse_all(input);
+
+    let p = match process(&lines) {
+        Ok(p) => p,
+        Err(e) => {
+            println!("invalid input: {}", e);
+            return;
+        }
+    };
+
+    let reduce = match super_mod(&p) {
+        Some(sm) => Relief::Modulo(sm),
+        None => Relief::None,
+    };
+    let mut parts = vec![
+        (Relief::DivideBy(3), PART_1_ROUNDS),
+        (reduce, PART_2_ROUNDS),
+    ];
+    // without a modulus worry levels don't repeat, so there's no cycle to
+    // extrapolate from
+    if let Relief::Modulo(_) = reduce {
+        parts.push((reduce, HUGE_ROUNDS));
+    } else {
+        println!(
+            "{} rounds skipped, the tests have no common modulus",
+            HUGE_ROUNDS
+        );
+    }
+
+    for (relief, rounds) in parts {
+        println!("{} rounds, relief {:?}", rounds, relief);
+        match compute(&p, relief, rounds) {
+            Ok(counts) => {
+                for (m, cnt) in counts.iter().enumerate() {
+                    println!("Monkey#{m} inspected items {} times", cnt);
+                }
+
+                let mut lb = counts.clone();
+                lb.sort();
+                let lblen = lb.len();
+                println!(
+                    "two most active: {} * {} = {}",
+                    lb[lblen - 1],
+                    lb[lblen - 2],
+                    lb[lblen - 1] as u128 * lb[lblen - 2] as u128
+                );
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    let text = serialize(&p);
+    let round_trip = process(&text.lines().map(String::from).collect::<Vec<_>>());
     println!(
-        "two most active: {} * {} = {}",
-        lb[lblen - 1],
-        lb[lblen - 2],
-        lb[lblen - 1] * lb[lblen - 2]
+        "round-trip: {}",
+        if round_trip.is_ok_and(|rt| rt == p) {
+            "ok"
+        } else {
+            "mismatch"
+        }
     );
+
+    if args.first().map(|a| a.as_str()) == Some("dump") {
+        println!("{}", text);
+    }
+
+    if args.first().map(|a| a.as_str()) == Some("trace") {
+        match trace(&p, Relief::DivideBy(3), PART_1_ROUNDS) {
+            Ok(t) => {
+                println!("{}", t.paths_csv());
+                println!("{}", t.throws_csv());
+                println!("{}", t.held_csv());
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
 }
 
-fn compute<'a>(
-    mks: &mut [CMonkey<impl Fn(u64) -> u64 + 'a, impl Fn(u64) -> usize + 'a>],
-    div3: bool,
-    max_iter: usize,
-) -> Vec<usize> {
+/// Inspection count of every monkey after `rounds` rounds.
+///
+/// Items never affect each other, so each one is followed on its own. Its
+/// state at the start of a round is `(holder, worry)`, once a state comes
+/// back the item is in a cycle and the remaining rounds are extrapolated.
+/// An item that doesn't cycle within `MAX_CYCLE_SEARCH` rounds is an error.
+fn compute(mks: &[Monkey], relief: Relief, rounds: u64) -> Result<Vec<u64>, SimError> {
     let mlen = mks.len();
-
-    for _i in 0..max_iter {
-        // println!("iter={i}");
-        for mi in 0..mlen {
-            let ilen = mks[mi].items.len();
-            for x in 0..ilen {
-                mks[mi].inspect_cnt += 1;
-                let mut wr = (mks[mi].transform_fn)(mks[mi].items[x]);
-                if div3 {
-                    wr = wr / 3;
-                } else {
-                    wr = wr % mks[mi].super_mod;
+    let mut total = vec![0_u64; mlen];
+
+    for (holder, worry) in mks
+        .iter()
+        .enumerate()
+        .flat_map(|(mi, m)| m.items.iter().map(move |w| (mi, *w)))
+    {
+        let mut state = (holder, worry);
+        let mut seen: HashMap<(usize, Worry), u64> = HashMap::new();
+        // cum[r] = inspections of this item before round `r`
+        let mut cum: Vec<Vec<u64>> = vec![vec![0; mlen]];
+
+        let mut path = vec![];
+        let mut r = 0;
+        while r < rounds {
+            if let Some(&r0) = seen.get(&state) {
+                let r0 = r0 as usize;
+                let period = r - r0 as u64;
+                let (full, rest) = ((rounds - r) / period, ((rounds - r) % period) as usize);
+                let last = cum.last().unwrap().clone();
+                for m in 0..mlen {
+                    let per_cycle = last[m] - cum[r0][m];
+                    cum.last_mut().unwrap()[m] +=
+                        full * per_cycle + (cum[r0 + rest][m] - cum[r0][m]);
                 }
-                let throw_target = (mks[mi].throw_fn)(wr);
-                mks[throw_target].items.push(wr);
+                break;
+            }
+            if r >= MAX_CYCLE_SEARCH {
+                return Err(SimError::NoCycle { rounds: r });
             }
-            mks[mi].items.clear();
+            seen.insert(state, r);
+
+            let mut cnt = cum.last().unwrap().clone();
+            state = item_round(mks, relief, state, &mut cnt, &mut path)?;
+            path.clear();
+            cum.push(cnt);
+            r += 1;
+        }
+
+        for (t, c) in total.iter_mut().zip(cum.last().unwrap()) {
+            *t += c;
+        }
+    }
+
+    Ok(total)
+}
+
+/// Plays one round for a single item, adding its inspections to `cnt` and
+/// the monkeys that inspected it to `path`.
+///
+/// Monkeys play in order, so an item thrown to a later monkey is inspected
+/// again within the same round.
+fn item_round(
+    mks: &[Monkey],
+    relief: Relief,
+    (mut holder, mut worry): (usize, Worry),
+    cnt: &mut [u64],
+    path: &mut Vec<usize>,
+) -> Result<(usize, Worry), SimError> {
+    loop {
+        let m = &mks[holder];
+        cnt[holder] += 1;
+        path.push(holder);
+
+        let overflow = SimError::Overflow {
+            monkey: holder,
+            worry,
+        };
+        let wr = m.op.eval(worry).ok_or(overflow)?;
+        worry = match relief {
+            Relief::DivideBy(d) => wr / d,
+            Relief::Modulo(sm) => wr.rem_euclid(sm),
+            Relief::None => wr,
+        };
+
+        let target = if m.test.check(worry) {
+            m.if_true
+        } else {
+            m.if_false
+        };
+        if target >= mks.len() {
+            return Err(SimError::NoSuchMonkey {
+                monkey: holder,
+                target,
+            });
+        }
+
+        let same_round = target > holder;
+        holder = target;
+        if !same_round {
+            return Ok((holder, worry));
+        }
+    }
+}
+
+/// Plays every round for every item, recording their paths
+fn trace(mks: &[Monkey], relief: Relief, rounds: u64) -> Result<Trace, SimError> {
+    let mut cnt = vec![0; mks.len()];
+    let mut paths = vec![];
+
+    for (holder, worry) in mks
+        .iter()
+        .enumerate()
+        .flat_map(|(mi, m)| m.items.iter().map(move |w| (mi, *w)))
+    {
+        let mut state = (holder, worry);
+        let mut item = vec![];
+        for _ in 0..rounds {
+            let mut path = vec![];
+            state = item_round(mks, relief, state, &mut cnt, &mut path)?;
+            path.push(state.0);
+            item.push(path);
         }
+        paths.push(item);
     }
 
-    let mut freqs = vec![0; mlen];
-    for m in 0..mlen {
-        println!("Monkey#{m} inspected items {} times", mks[m].inspect_cnt);
-        freqs[m] = mks[m].inspect_cnt;
+    Ok(Trace {
+        monkeys: mks.len(),
+        paths,
+    })
+}
+
+fn gcd(a: Worry, b: Worry) -> Worry {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
     }
+}
 
-    freqs.sort();
+fn lcm(a: Worry, b: Worry) -> Worry {
+    a / gcd(a, b) * b
+}
 
-    freqs
+/// Least common multiple of every divisor (and of any constant the
+/// operations take a modulo of), keeping worry levels modulo it doesn't
+/// change any test outcome.
+///
+/// Only holds when every test is a divisibility test and no operation
+/// divides.
+fn super_mod(mks: &[Monkey]) -> Option<Worry> {
+    mks.iter().try_fold(1, |acc, m| {
+        let n = match m.test {
+            Test::DivisibleBy(n) | Test::NotDivisibleBy(n) if n != 0 => n.abs(),
+            _ => return None,
+        };
+        Some(lcm(lcm(acc, n), m.op.modulus()?))
+    })
 }
 
-fn process(lines: &[String]) -> Vec<CMonkey<impl Fn(u64) -> u64 + '_, impl Fn(u64) -> usize + '_>> {
-    let len = lines.len() / 6;
-    let mut mks = vec![];
-    let mut smod = 1;
-    for i in 0..len {
-        // 6 lines per-monkey
-        let items = &lines[(i * 6 + 1) as usize];
-        let ops = &lines[(i * 6 + 2) as usize];
-        let divisor = &lines[(i * 6 + 3) as usize];
-        let true_cond = &lines[(i * 6 + 4) as usize];
-        let false_cond = &lines[(i * 6 + 5) as usize];
-
-        let items = items
-            .split(":")
-            .last()
-            .unwrap()
-            .split(",")
-            .map(|s| s.trim().parse::<u64>().unwrap());
-
-        let ops = ops
-            .split(":")
-            .last()
-            .unwrap()
-            .split("=")
-            .last()
-            .unwrap()
-            .split(" ")
-            .filter(|s| s.len() > 0)
-            .map(|s| match s.trim() {
-                "old" => OpSymbol::ValOld,
-                "+" => OpSymbol::OpAdd,
-                "*" => OpSymbol::OpMult,
-                _ => OpSymbol::Val(s.parse::<u64>().unwrap()),
+/// Monkeys in the puzzle format, separated by a blank line
+fn serialize(mks: &[Monkey]) -> String {
+    let blocks: Vec<String> = mks.iter().map(|m| m.to_string()).collect();
+    blocks.join("\n\n")
+}
+
+/// Reads the monkeys section by section, each one starts at its
+/// `Monkey N:` header.
+///
+/// Blank lines are skipped, fields can come in any order within a section
+/// and sections in any order within the input.
+fn process(lines: &[String]) -> Result<Vec<Monkey>, ParseError> {
+    let mut sections: Vec<Section> = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let (ln, l) = (i + 1, line.trim());
+        if l.is_empty() {
+            continue;
+        }
+
+        if let Some(id) = l.strip_prefix("Monkey ").and_then(|r| r.strip_suffix(':')) {
+            let id = id
+                .parse::<usize>()
+                .map_err(|_| ParseError::Number { line: ln })?;
+            sections.push(Section {
+                id,
+                line: ln,
+                fields: vec![],
             });
-        let ops: Vec<OpSymbol> = ops.collect();
-
-        let divisor = divisor
-            .split("divisible by")
-            .last()
-            .unwrap()
-            .trim()
-            .parse::<u64>()
-            .unwrap();
-
-        let true_cond = true_cond
-            .split("throw to monkey")
-            .last()
-            .unwrap()
-            .trim()
-            .parse::<usize>()
-            .unwrap();
-
-        let false_cond = false_cond
-            .split("throw to monkey")
-            .last()
-            .unwrap()
-            .trim()
-            .parse::<usize>()
-            .unwrap();
-
-        smod = smod * divisor;
-
-        mks.push(CMonkey {
-            inspect_cnt: 0,
-            items: items.collect(),
-            super_mod: 0,
-            transform_fn: move |wl| -> u64 {
-                let opr = &ops;
-
-                let arg1 = &opr[0];
-                let operator = &opr[1];
-                let arg2 = &opr[2];
-
-                let arg1_wl = arg1.is_val_old();
-                let arg2_wl = arg2.is_val_old();
-
-                match operator {
-                    OpSymbol::OpAdd => {
-                        if arg1_wl && arg2_wl {
-                            2 * wl
-                        } else {
-                            let v = if arg1_wl {
-                                arg2.get_value()
-                            } else {
-                                arg1.get_value()
-                            };
-
-                            wl + v
-                        }
-                    }
-
-                    OpSymbol::OpMult => {
-                        if arg1_wl && arg2_wl {
-                            wl * wl
-                        } else {
-                            let v = if arg1_wl {
-                                arg2.get_value()
-                            } else {
-                                arg1.get_value()
-                            };
-
-                            wl * v
-                        }
-                    }
-
-                    _ => unimplemented!(),
-                }
-            },
-            throw_fn: move |wl| -> usize {
-                if wl % divisor == 0 {
-                    true_cond
+        } else {
+            let sec = sections.last_mut().ok_or(ParseError::Orphan { line: ln })?;
+            sec.fields.push((ln, l));
+        }
+    }
+
+    sections.sort_by_key(|sec| sec.id);
+    let mut mks = vec![];
+    for (i, sec) in sections.iter().enumerate() {
+        if sec.id != i {
+            return Err(ParseError::MonkeyId { line: sec.line });
+        }
+
+        mks.push(parse_monkey(sec)?);
+    }
+
+    Ok(mks)
+}
+
+/// Lines of one monkey, `line` is its header
+struct Section<'a> {
+    id: usize,
+    line: usize,
+    fields: Vec<(usize, &'a str)>,
+}
+
+fn parse_monkey(sec: &Section) -> Result<Monkey, ParseError> {
+    let mut items = None;
+    let mut op = None;
+    let mut test = None;
+    let mut if_true = None;
+    let mut if_false = None;
+
+    for &(line, l) in sec.fields.iter() {
+        let (key, val) = l.split_once(':').ok_or(ParseError::UnknownField { line })?;
+        let val = val.trim();
+        let dup = || ParseError::DuplicateField { line };
+
+        match key {
+            "Starting items" => {
+                let ws = val
+                    .split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.parse::<Worry>())
+                    .collect::<Result<Vec<Worry>, _>>()
+                    .map_err(|_| ParseError::Number { line })?;
+                items.replace(ws).map_or(Ok(()), |_| Err(dup()))?;
+            }
+            "Operation" => {
+                let e = val
+                    .strip_prefix("new =")
+                    .ok_or(ParseError::Expr { line, col: 0 })?;
+                let e = parse_expr(e).map_err(|col| ParseError::Expr { line, col })?;
+                op.replace(e).map_or(Ok(()), |_| Err(dup()))?;
+            }
+            "Test" => {
+                let t = parse_test(val).ok_or(ParseError::Test { line })?;
+                test.replace(t).map_or(Ok(()), |_| Err(dup()))?;
+            }
+            "If true" | "If false" => {
+                let target = parse_target(val).ok_or(ParseError::Number { line })?;
+                let slot = if key == "If true" {
+                    &mut if_true
                 } else {
-                    false_cond
-                }
-            },
-        })
+                    &mut if_false
+                };
+                slot.replace(target).map_or(Ok(()), |_| Err(dup()))?;
+            }
+            _ => return Err(ParseError::UnknownField { line }),
+        }
+    }
+
+    let missing = |field| ParseError::MissingField {
+        line: sec.line,
+        field,
+    };
+    Ok(Monkey {
+        id: sec.id,
+        items: items.ok_or(missing("Starting items"))?,
+        op: op.ok_or(missing("Operation"))?,
+        test: test.ok_or(missing("Test"))?,
+        if_true: if_true.ok_or(missing("If true"))?,
+        if_false: if_false.ok_or(missing("If false"))?,
+    })
+}
+
+fn parse_target(text: &str) -> Option<usize> {
+    text.strip_prefix("throw to monkey")?
+        .trim()
+        .parse::<usize>()
+        .ok()
+}
+
+fn parse_test(text: &str) -> Option<Test> {
+    let (kind, num) = text.trim().rsplit_once(' ')?;
+    let num = num.parse::<Worry>().ok()?;
+
+    match kind {
+        "divisible by" | "not divisible by" if num == 0 => None,
+        "divisible by" => Some(Test::DivisibleBy(num)),
+        "not divisible by" => Some(Test::NotDivisibleBy(num)),
+        "greater than" => Some(Test::GreaterThan(num)),
+        "less than" => Some(Test::LessThan(num)),
+        "equal to" => Some(Test::EqualTo(num)),
+        _ => None,
+    }
+}
+
+/// Parses an arithmetic expression over `old` and integers with `+ - * / %`,
+/// unary minus and parentheses. On failure returns the offending char offset.
+///
+/// ```text
+/// expr   := term (('+' | '-') term)*
+/// term   := factor (('*' | '/' | '%') factor)*
+/// factor := number | "old" | '(' expr ')' | '-' factor
+/// ```
+fn parse_expr(text: &str) -> Result<Expr, usize> {
+    let mut p = ExprParser {
+        chars: text.chars().peekable(),
+        pos: 0,
+    };
+    let e = p.expr()?;
+    p.skip_ws();
+    if p.chars.peek().is_some() {
+        return Err(p.pos);
+    }
+
+    Ok(e)
+}
+
+struct ExprParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn skip_ws(&mut self) {
+        while self.chars.next_if(|ch| ch.is_whitespace()).is_some() {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes the next non-space char if it is one of `ops`
+    fn next_op(&mut self, ops: &[char]) -> Option<char> {
+        self.skip_ws();
+        let ch = self.chars.next_if(|ch| ops.contains(ch))?;
+        self.pos += 1;
+        Some(ch)
     }
 
-    for m in 0..mks.len() {
-        mks[m].super_mod = smod;
+    fn expr(&mut self) -> Result<Expr, usize> {
+        let mut lhs = self.term()?;
+        while let Some(ch) = self.next_op(&['+', '-']) {
+            let op = if ch == '+' {
+                BinOp::Add
+            } else {
+                BinOp::Subtract
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+
+        Ok(lhs)
     }
 
-    mks
+    fn term(&mut self) -> Result<Expr, usize> {
+        let mut lhs = self.factor()?;
+        while let Some(ch) = self.next_op(&['*', '/', '%']) {
+            let op = match ch {
+                '*' => BinOp::Multiply,
+                '/' => BinOp::Divide,
+                _ => BinOp::Modulo,
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.factor()?));
+        }
+
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr, usize> {
+        if self.next_op(&['-']).is_some() {
+            return Ok(Expr::Negate(Box::new(self.factor()?)));
+        }
+        if self.next_op(&['(']).is_some() {
+            let e = self.expr()?;
+            return match self.next_op(&[')']) {
+                Some(_) => Ok(e),
+                None => Err(self.pos),
+            };
+        }
+
+        let start = self.pos;
+        let mut word = String::new();
+        while let Some(ch) = self.chars.next_if(|ch| ch.is_ascii_alphanumeric()) {
+            word.push(ch);
+            self.pos += 1;
+        }
+
+        match word.as_str() {
+            "old" => Ok(Expr::Old),
+            _ => word.parse::<Worry>().map(Expr::Constant).map_err(|_| start),
+        }
+    }
 }
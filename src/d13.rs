@@ -5,6 +5,8 @@ use std::{
     slice,
 };
 
+use serde_json::Value;
+
 use crate::common;
 
 #[derive(Clone, Eq)]
@@ -13,78 +15,139 @@ enum ListEl {
     Nest(Vec<ListEl>),
 }
 
-impl ListEl {
-    /// Parses the input string iteratively instead of recursive by using additional `Vec<ListEl>`
-    ///
-    /// ## Warning
-    /// The `input` is assumed to be properly formatted (no missing `[` or `]`), in case the `input` is not properly formatted,
-    /// there will be some undefined-behavior or incorrect parsing result.
-    ///
-    /// ## Todo
-    /// Add open/close validate
-    fn parse(input: &str) -> Vec<ListEl> {
-        let mut root: Vec<ListEl> = vec![];
-        let chrs = input.as_bytes();
-        let mut lvl = 0;
-
-        let mut working_stack = vec![];
-        let mut acc = vec![];
-        let mut i = 0;
-        while i < chrs.len() {
-            let c = chrs[i];
-            if c == b'[' {
-                lvl += 1;
-            } else if c == b',' || c == b']' {
-                if acc.len() > 0 {
-                    let s = String::from_utf8(acc).unwrap().parse::<i32>().unwrap();
-                    acc = vec![];
-
-                    if lvl > 1 {
-                        // Not at the root level, store the result to the working stack
-                        working_stack.push((lvl, ListEl::Num(s)));
-                    } else {
-                        // At root, just store the value as is
-                        root.push(ListEl::Num(s));
-                    }
-                }
+/// Reason a packet can't be parsed, `pos` is the 0-based byte offset
+#[derive(Debug)]
+enum ParseError {
+    UnexpectedChar {
+        pos: usize,
+        found: char,
+    },
+    UnexpectedEnd {
+        pos: usize,
+    },
+    BadNumber {
+        pos: usize,
+    },
+    /// A pair block with a single packet
+    MissingPacket,
+    /// A pair block with more than two packets
+    ExtraPacket,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { pos, found } => {
+                write!(f, "unexpected `{}` at {}", found, pos)
+            }
+            ParseError::UnexpectedEnd { pos } => write!(f, "unexpected end at {}", pos),
+            ParseError::BadNumber { pos } => write!(f, "invalid number at {}", pos),
+            ParseError::MissingPacket => write!(f, "pair is missing its second packet"),
+            ParseError::ExtraPacket => write!(f, "pair has more than two packets"),
+        }
+    }
+}
+
+/// Recursive-descent parser over the packet grammar, whitespace is allowed
+/// between any two tokens
+///
+/// ```text
+/// value := number | list
+/// list  := '[' (value (',' value)*)? ']'
+/// number := '-'? digit+
+/// ```
+struct PacketParser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl PacketParser<'_> {
+    fn skip_ws(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
 
-                if c == b']' {
-                    if lvl > 1 {
-                        // Not at the root level and about to leave this depth/level
-                        // reduce the value into a single element
-                        let mut nest = vec![];
-                        while let Some(last_item) = working_stack.last() {
-                            if last_item.0 != lvl {
-                                break;
-                            }
-                            let last_item = working_stack.pop().unwrap();
-                            nest.push(last_item.1);
-                        }
-                        nest.reverse();
-
-                        if lvl > 2 {
-                            // Currently at the level deeper than 2, store the reduced value to the working stack
-                            // using the parent level (1-level above)
-                            working_stack.push((lvl - 1, ListEl::Nest(nest)));
-                        } else {
-                            // At level 2
-                            // parent is root, just commit/store the reduced value to the root since we are going back to root
-                            root.push(ListEl::Nest(nest));
-                        }
-                    }
-                    lvl -= 1;
+    /// Next non-space byte, without consuming it
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.bytes.get(self.pos) {
+            Some(b) => ParseError::UnexpectedChar {
+                pos: self.pos,
+                found: *b as char,
+            },
+            None => ParseError::UnexpectedEnd { pos: self.pos },
+        }
+    }
+
+    fn value(&mut self) -> Result<ListEl, ParseError> {
+        match self.peek() {
+            Some(b'[') => self.list(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn list(&mut self) -> Result<ListEl, ParseError> {
+        // skip `[`
+        self.pos += 1;
+        let mut items = vec![];
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(ListEl::Nest(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(ListEl::Nest(items));
                 }
-            } else {
-                acc.push(c);
+                _ => return Err(self.unexpected()),
             }
-            i += 1;
         }
+    }
 
-        // if working_stack.len() > 0 {}
+    fn number(&mut self) -> Result<ListEl, ParseError> {
+        let start = self.pos;
+        if self.bytes[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        while self.bytes.get(self.pos).is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
 
-        // println!("root: {:?}", root);
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|s| s.parse::<i32>().ok())
+            .map(ListEl::Num)
+            .ok_or(ParseError::BadNumber { pos: start })
+    }
+}
+
+impl ListEl {
+    /// Parses a whole packet, anything but whitespace after it is an error
+    fn parse(input: &str) -> Result<ListEl, ParseError> {
+        let mut p = PacketParser {
+            bytes: input.as_bytes(),
+            pos: 0,
+        };
+        let el = p.value()?;
+        if p.peek().is_some() {
+            return Err(p.unexpected());
+        }
 
-        root
+        Ok(el)
     }
 
     pub fn as_slice(&self) -> &[ListEl] {
@@ -100,21 +163,14 @@ impl Display for ListEl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ListEl::Num(n) => write!(f, "{}", n),
+            // same layout as the puzzle input
             ListEl::Nest(v) => {
-                if let Err(err) = write!(f, "[") {
-                    return Err(err);
+                write!(f, "[")?;
+                for (i, el) in v.iter().enumerate() {
+                    let sep = if i > 0 { "," } else { "" };
+                    write!(f, "{}{}", sep, el)?;
                 }
-
-                for i in 0..v.len() {
-                    let sep = if i > 0 { ", " } else { "" };
-                    if let Err(err) = write!(f, "{}{}", sep, v[i]) {
-                        return Err(err);
-                    }
-                }
-                if let Err(err) = write!(f, "]") {
-                    return Err(err);
-                }
-                Ok(())
+                write!(f, "]")
             }
         }
     }
@@ -148,13 +204,271 @@ impl PartialEq for ListEl {
     }
 }
 
-pub fn run(mut input: Lines<impl BufRead>) {
+impl From<&ListEl> for Value {
+    fn from(el: &ListEl) -> Self {
+        match el {
+            ListEl::Num(n) => Value::from(*n),
+            ListEl::Nest(v) => Value::Array(v.iter().map(Value::from).collect()),
+        }
+    }
+}
+
+/// Reason a JSON value isn't a packet, only arrays and `i32` numbers are
+#[derive(Debug)]
+enum JsonError {
+    BadNumber(serde_json::Number),
+    BadType(&'static str),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::BadNumber(n) => write!(f, "{} is not an i32", n),
+            JsonError::BadType(t) => write!(f, "{} can't be part of a packet", t),
+        }
+    }
+}
+
+impl TryFrom<&Value> for ListEl {
+    type Error = JsonError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => n
+                .as_i64()
+                .and_then(|n| i32::try_from(n).ok())
+                .map(ListEl::Num)
+                .ok_or_else(|| JsonError::BadNumber(n.clone())),
+            Value::Array(v) => v
+                .iter()
+                .map(ListEl::try_from)
+                .collect::<Result<_, _>>()
+                .map(ListEl::Nest),
+            Value::Null => Err(JsonError::BadType("null")),
+            Value::Bool(_) => Err(JsonError::BadType("a bool")),
+            Value::String(_) => Err(JsonError::BadType("a string")),
+            Value::Object(_) => Err(JsonError::BadType("an object")),
+        }
+    }
+}
+
+/// Reason a byte string can't be decoded, `pos` is the 0-based byte offset
+#[derive(Debug)]
+enum DecodeError {
+    UnexpectedEnd {
+        pos: usize,
+    },
+    /// Varint longer than 64 bits, or number outside of `i32`
+    Overflow {
+        pos: usize,
+    },
+    /// List claims more items than there are bytes left
+    BadLength {
+        pos: usize,
+    },
+    TrailingBytes {
+        pos: usize,
+    },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd { pos } => write!(f, "unexpected end at {}", pos),
+            DecodeError::Overflow { pos } => write!(f, "value overflows at {}", pos),
+            DecodeError::BadLength { pos } => write!(f, "list too long at {}", pos),
+            DecodeError::TrailingBytes { pos } => write!(f, "trailing bytes at {}", pos),
+        }
+    }
+}
+
+/// Compact binary form of a packet, every element is a single LEB128 varint
+/// header:
+///
+/// ```text
+/// number := varint(zigzag(n) << 1)
+/// list   := varint(len << 1 | 1) element*
+/// ```
+///
+/// Small numbers and short lists take one byte, `[]` encodes as `01`.
+fn encode(el: &ListEl) -> Vec<u8> {
+    let mut out = vec![];
+    encode_into(el, &mut out);
+    out
+}
+
+fn encode_into(el: &ListEl, out: &mut Vec<u8>) {
+    match el {
+        ListEl::Num(n) => {
+            let zz = ((*n << 1) ^ (*n >> 31)) as u32;
+            write_varint(u64::from(zz) << 1, out);
+        }
+        ListEl::Nest(v) => {
+            write_varint((v.len() as u64) << 1 | 1, out);
+            for el in v {
+                encode_into(el, out);
+            }
+        }
+    }
+}
+
+fn write_varint(mut x: u64, out: &mut Vec<u8>) {
+    while x >= 0x80 {
+        out.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+/// Inverse of `encode`, the whole input must be a single packet
+fn decode(bytes: &[u8]) -> Result<ListEl, DecodeError> {
+    let mut pos = 0;
+    let el = decode_from(bytes, &mut pos)?;
+    if pos != bytes.len() {
+        return Err(DecodeError::TrailingBytes { pos });
+    }
+
+    Ok(el)
+}
+
+fn decode_from(bytes: &[u8], pos: &mut usize) -> Result<ListEl, DecodeError> {
+    let start = *pos;
+    let header = read_varint(bytes, pos)?;
+    if header & 1 == 0 {
+        let zz = u32::try_from(header >> 1).map_err(|_| DecodeError::Overflow { pos: start })?;
+        return Ok(ListEl::Num((zz >> 1) as i32 ^ -((zz & 1) as i32)));
+    }
+
+    // every element takes at least one byte
+    let len = header >> 1;
+    if len > (bytes.len() - *pos) as u64 {
+        return Err(DecodeError::BadLength { pos: start });
+    }
+    let mut items = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        items.push(decode_from(bytes, pos)?);
+    }
+
+    Ok(ListEl::Nest(items))
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, DecodeError> {
+    let start = *pos;
+    let mut x = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *bytes
+            .get(*pos)
+            .ok_or(DecodeError::UnexpectedEnd { pos: *pos })?;
+        *pos += 1;
+        let part = u64::from(b & 0x7f);
+        if shift == 63 && part > 1 {
+            return Err(DecodeError::Overflow { pos: start });
+        }
+        x |= part << shift;
+        if b & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+
+    Err(DecodeError::Overflow { pos: start })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Comparison of a pair, step by step
+struct Explain {
+    order: Ordering,
+    /// Puzzle style trace, one line per step
+    steps: Vec<String>,
+    /// Index of the deciding element at each nesting level, the depth is its
+    /// length. Empty when the packets are equal.
+    decided_at: Vec<usize>,
+}
+
+/// Walks `a` vs `b` the same way `ListEl::cmp` does, recording every step
+fn explain(a: &ListEl, b: &ListEl) -> Explain {
+    let mut ex = Explain {
+        order: Ordering::Equal,
+        steps: vec![],
+        decided_at: vec![],
+    };
+    let mut path = vec![];
+    ex.order = explain_step(a, b, &mut path, &mut ex);
+    debug_assert_eq!(ex.order, a.cmp(b));
+
+    ex
+}
+
+fn explain_step(a: &ListEl, b: &ListEl, path: &mut Vec<usize>, ex: &mut Explain) -> Ordering {
+    let indent = "  ".repeat(path.len());
+    ex.steps.push(format!("{}- Compare {} vs {}", indent, a, b));
+
+    let order = match (a, b) {
+        (ListEl::Num(x), ListEl::Num(y)) => x.cmp(y),
+        (ListEl::Num(_), ListEl::Nest(_)) | (ListEl::Nest(_), ListEl::Num(_)) => {
+            let side = if let ListEl::Num(_) = a {
+                "left"
+            } else {
+                "right"
+            };
+            ex.steps.push(format!(
+                "{}  - Mixed types; convert {} to [{}] and retry comparison",
+                indent,
+                side,
+                if let ListEl::Num(_) = a { a } else { b }
+            ));
+            let (la, lb) = (
+                ListEl::Nest(a.as_slice().to_vec()),
+                ListEl::Nest(b.as_slice().to_vec()),
+            );
+            return explain_step(&la, &lb, path, ex);
+        }
+        (ListEl::Nest(va), ListEl::Nest(vb)) => {
+            for (i, (ea, eb)) in va.iter().zip(vb.iter()).enumerate() {
+                path.push(i);
+                let order = explain_step(ea, eb, path, ex);
+                path.pop();
+                if order.is_ne() {
+                    return order;
+                }
+            }
+
+            let order = va.len().cmp(&vb.len());
+            if order.is_ne() {
+                let side = if order.is_lt() { "Left" } else { "Right" };
+                ex.steps
+                    .push(format!("{}  - {} side ran out of items", indent, side));
+                ex.decided_at = path.clone();
+                ex.decided_at.push(va.len().min(vb.len()));
+            }
+            return order;
+        }
+    };
+
+    if order.is_ne() {
+        let side = if order.is_lt() { "Left" } else { "Right" };
+        ex.steps
+            .push(format!("{}  - {} side is smaller", indent, side));
+        ex.decided_at = path.clone();
+    }
+    order
+}
+
+pub fn run(mut input: Lines<impl BufRead>, args: &[String]) {
     let mut pair_lines = common::parse_mut(&mut input);
     let mut pairs = vec![];
     let mut pn = 1;
     let mut sum = 0;
-    while pair_lines.len() > 0 {
-        let (p1, p2, ok) = process(&pair_lines);
+    while !pair_lines.is_empty() {
+        let (p1, p2, ok) = match process(&pair_lines) {
+            Ok(res) => res,
+            Err((line, e)) => {
+                println!("pair #{pn} line {line}: {}", e);
+                return;
+            }
+        };
 
         if ok {
             sum += pn;
@@ -169,14 +483,14 @@ pub fn run(mut input: Lines<impl BufRead>) {
         pn += 1;
     }
 
-    // [Nest([Num(2)])]
-    let div_1 = vec![ListEl::Nest(vec![ListEl::Num(2)])];
-    // [Nest([Num(6)])]
-    let div_2 = vec![ListEl::Nest(vec![ListEl::Num(6)])];
+    // [[2]]
+    let div_1 = ListEl::Nest(vec![ListEl::Nest(vec![ListEl::Num(2)])]);
+    // [[6]]
+    let div_2 = ListEl::Nest(vec![ListEl::Nest(vec![ListEl::Num(6)])]);
 
     let mut pos_1 = 1;
     let mut pos_2 = pos_1 + 1;
-    for p in pairs {
+    for p in pairs.iter() {
         if let Ordering::Less | Ordering::Equal = p.cmp(&div_1) {
             pos_1 += 1;
         }
@@ -185,24 +499,87 @@ pub fn run(mut input: Lines<impl BufRead>) {
         }
     }
 
-    // for p in pairs {
-    //     println!("{:?}", p);
-    // }
-
     println!("sum index: {}", sum);
     println!("divider: {} * {} = {}", pos_1, pos_2, pos_1 * pos_2);
+
+    match args.first().map(|a| a.as_str()) {
+        Some("sort") => {
+            let mut sorted = pairs.clone();
+            sorted.push(div_1);
+            sorted.push(div_2);
+            sorted.sort();
+            for p in sorted {
+                println!("{}", p);
+            }
+        }
+        Some("explain") => {
+            let pn = args
+                .get(1)
+                .and_then(|a| a.parse::<usize>().ok())
+                .unwrap_or(1);
+            let Some(pair) = pn.checked_sub(1).and_then(|i| pairs.get(i * 2..i * 2 + 2)) else {
+                println!("no pair #{pn}");
+                return;
+            };
+
+            let ex = explain(&pair[0], &pair[1]);
+            println!("== Pair {} ==", pn);
+            for step in ex.steps.iter() {
+                println!("{}", step);
+            }
+            println!(
+                "=> {:?}, decided at depth {} by index path {:?}",
+                ex.order,
+                ex.decided_at.len(),
+                ex.decided_at
+            );
+        }
+        Some("json") => {
+            let pairs: Vec<Value> = pairs
+                .chunks(2)
+                .map(|pair| Value::Array(pair.iter().map(Value::from).collect()))
+                .collect();
+            println!("{}", Value::Array(pairs));
+        }
+        Some("encode") => {
+            let (mut text_len, mut bin_len) = (0, 0);
+            for p in pairs.iter() {
+                let bin = encode(p);
+                println!("{} {}", to_hex(&bin), p);
+
+                match decode(&bin) {
+                    Ok(back) if back.to_string() == p.to_string() => {}
+                    Ok(back) => println!("round-trip mismatch: {}", back),
+                    Err(e) => println!("round-trip failed: {}", e),
+                }
+                match ListEl::try_from(&Value::from(p)) {
+                    Ok(back) if back.to_string() == p.to_string() => {}
+                    Ok(back) => println!("json round-trip mismatch: {}", back),
+                    Err(e) => println!("json round-trip failed: {}", e),
+                }
+
+                text_len += p.to_string().len();
+                bin_len += bin.len();
+            }
+            println!("text bytes: {}, encoded bytes: {}", text_len, bin_len);
+        }
+        _ => {}
+    }
 }
 
-fn process(lines: &[String]) -> (Vec<ListEl>, Vec<ListEl>, bool) {
-    if lines.len() != 2 {
-        return (vec![], vec![], false);
+/// Parses a pair, errors come with the line of the pair they are on
+fn process(lines: &[String]) -> Result<(ListEl, ListEl, bool), (usize, ParseError)> {
+    match lines.len() {
+        0 | 1 => return Err((lines.len() + 1, ParseError::MissingPacket)),
+        2 => {}
+        _ => return Err((3, ParseError::ExtraPacket)),
     }
 
-    let p1 = ListEl::parse(lines[0].as_str());
+    let p1 = ListEl::parse(lines[0].as_str()).map_err(|e| (1, e))?;
 
-    let p2 = ListEl::parse(lines[1].as_str());
+    let p2 = ListEl::parse(lines[1].as_str()).map_err(|e| (2, e))?;
 
     let ok = p1 <= p2;
 
-    (p1, p2, ok)
+    Ok((p1, p2, ok))
 }
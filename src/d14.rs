@@ -1,5 +1,6 @@
 use std::{
     collections::{HashSet, VecDeque},
+    fmt::Display,
     io::{BufRead, Lines},
 };
 
@@ -8,127 +9,299 @@ use crate::common;
 const AIR: u8 = 0;
 const WALL: u8 = 1;
 const SAND: u8 = 2;
-const FLOOR_Y_REL: usize = 2;
+const FLOOR_Y_REL: i32 = 2;
 
-const SAND_SRC: [i32; 2] = [500, 0];
+const SAND_SRC: Point = Point { x: 500, y: 0 };
 const DIR_X: [i32; 3] = [0, -1, 1];
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Point {
     pub x: i32,
     pub y: i32,
 }
 
-pub fn run(mut input: Lines<impl BufRead>) {
-    let lines = common::parse(input);
+/// Rules for pouring sand into a cave
+#[derive(Clone)]
+struct SandConfig {
+    /// Where grains enter, they take turns one grain at a time.
+    /// Sources must not be above `y = 0` nor on or below the floor.
+    sources: Vec<Point>,
+    /// Floor this many rows (at least 1) below the lowest rock, `None` for
+    /// an abyss
+    floor: Option<i32>,
+    /// x steps a grain tries in order, every step falls by one row
+    fall_dx: Vec<i32>,
+}
 
-    let (mut grid, max_y) = process(&lines);
+impl Default for SandConfig {
+    fn default() -> Self {
+        SandConfig {
+            sources: vec![SAND_SRC],
+            floor: Some(FLOOR_Y_REL),
+            fall_dx: DIR_X.to_vec(),
+        }
+    }
+}
 
-    let sim_res_1 = simulate(&mut grid, max_y, false);
-    println!("(p1) all-wall filled: {}", sim_res_1);
-    let sim_res_2 = simulate(&mut grid, max_y, true);
-    println!("(p2) flooded: {}", sim_res_1 + sim_res_2);
+/// Reason a cave can't be set up for a `SandConfig`
+enum SetupError {
+    FloorTooHigh { offset: i32 },
+    SourceOutside { source: Point },
+    RockAboveTop { rock: Point },
+}
+
+impl Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetupError::FloorTooHigh { offset } => write!(
+                f,
+                "floor offset {} must put the floor below the lowest rock",
+                offset
+            ),
+            SetupError::SourceOutside { source } => write!(
+                f,
+                "source {},{} is above y=0 or not above the floor",
+                source.x, source.y
+            ),
+            SetupError::RockAboveTop { rock } => {
+                write!(f, "rock {},{} is above y=0", rock.x, rock.y)
+            }
+        }
+    }
+}
 
-    if grid.len() > 0 {}
+/// Column-major cave map (`cols[x - x0][y]`), columns are added on either
+/// side as the sand spreads out
+#[derive(Clone)]
+struct Cave {
+    /// x of the first column
+    x0: i32,
+    cols: VecDeque<Vec<u8>>,
+    /// y of the lowest rock
+    bottom: i32,
+    /// y of the floor, if there is one
+    floor: Option<i32>,
 }
 
-fn simulate(grid: &mut VecDeque<Vec<u8>>, max_y: usize, flood: bool) -> usize {
-    if flood {
-        let mut last_drop: VecDeque<Point> = VecDeque::new();
-        let mut cnt = 0;
-
-        last_drop.push_front(Point {
-            x: SAND_SRC[0],
-            y: SAND_SRC[1],
-        });
-
-        while !last_drop.is_empty() {
-            let cur_pos = last_drop.pop_back().unwrap();
-            let cx = cur_pos.x as usize;
-            let cy = cur_pos.y as usize;
-
-            if grid[cx][cy] == AIR {
-                grid[cx][cy] = SAND;
-                cnt += 1;
-                for dx in DIR_X {
-                    let nx = cur_pos.x + dx;
-                    let ny = cur_pos.y + 1;
-
-                    if nx < 0 || ny as usize >= max_y {
-                        if nx < 0 {}
-                        continue;
-                    }
-                    last_drop.push_front(Point { x: nx, y: ny });
+impl Cave {
+    fn new(rocks: &HashSet<Point>, cfg: &SandConfig) -> Result<Self, SetupError> {
+        if let Some(offset) = cfg.floor.filter(|off| *off < 1) {
+            return Err(SetupError::FloorTooHigh { offset });
+        }
+        if let Some(rock) = rocks.iter().find(|p| p.y < 0) {
+            return Err(SetupError::RockAboveTop { rock: *rock });
+        }
+
+        let bottom = rocks.iter().map(|p| p.y).max().unwrap_or(0);
+        let floor = cfg.floor.map(|off| bottom + off);
+        if let Some(source) = cfg
+            .sources
+            .iter()
+            .find(|s| s.y < 0 || floor.is_some_and(|f| s.y >= f))
+        {
+            return Err(SetupError::SourceOutside { source: *source });
+        }
+
+        let mut cave = Cave {
+            x0: rocks.iter().map(|p| p.x).min().unwrap_or(SAND_SRC.x),
+            cols: VecDeque::new(),
+            bottom,
+            floor,
+        };
+        for p in rocks {
+            cave.set(*p, WALL);
+        }
+
+        Ok(cave)
+    }
+
+    /// Rows a column needs, nothing settles on or below the floor and
+    /// nothing stays below the lowest rock without one
+    fn height(&self) -> usize {
+        self.floor.unwrap_or(self.bottom + 1) as usize
+    }
+
+    fn get(&self, p: Point) -> u8 {
+        if Some(p.y) == self.floor {
+            return WALL;
+        }
+
+        usize::try_from(p.x - self.x0)
+            .ok()
+            .and_then(|cx| self.cols.get(cx))
+            .and_then(|col| col.get(p.y as usize))
+            .copied()
+            .unwrap_or(AIR)
+    }
+
+    fn set(&mut self, p: Point, v: u8) {
+        let height = self.height();
+        if self.cols.is_empty() {
+            self.x0 = p.x;
+        }
+        while p.x < self.x0 {
+            self.cols.push_front(vec![AIR; height]);
+            self.x0 -= 1;
+        }
+        let cx = (p.x - self.x0) as usize;
+        if cx >= self.cols.len() {
+            self.cols.resize(cx + 1, vec![AIR; height]);
+        }
+
+        self.cols[cx][p.y as usize] = v;
+    }
+
+    /// Every cell holding sand
+    fn sand(&self) -> HashSet<Point> {
+        let mut sand = HashSet::new();
+        for (cx, col) in self.cols.iter().enumerate() {
+            for (y, v) in col.iter().enumerate() {
+                if *v == SAND {
+                    sand.insert(Point {
+                        x: self.x0 + cx as i32,
+                        y: y as i32,
+                    });
                 }
             }
         }
 
-        cnt
-    } else {
-        let mut last_drop: Vec<Point> = vec![];
-        last_drop.push(Point {
-            x: SAND_SRC[0],
-            y: SAND_SRC[1],
-        });
-
-        let mut cnt = 0;
+        sand
+    }
 
-        while !last_drop.is_empty() {
-            let cur_pos = last_drop.last().unwrap().clone();
-            let cx = cur_pos.x as usize;
-            let cy = cur_pos.y as usize;
+    /// Falls from the end of `path` until the grain rests, pushing every
+    /// cell it goes through. `None` when it falls into the abyss.
+    fn drop_grain(&self, path: &mut Vec<Point>, fall_dx: &[i32]) -> Option<Point> {
+        loop {
+            let cur = *path.last()?;
+            if self.floor.is_none() && cur.y >= self.bottom {
+                return None;
+            }
 
-            if (cur_pos.y + 1) as usize >= max_y {
-                break;
+            let next = fall_dx
+                .iter()
+                .map(|dx| Point {
+                    x: cur.x + dx,
+                    y: cur.y + 1,
+                })
+                .find(|p| self.get(*p) == AIR);
+            match next {
+                Some(p) => path.push(p),
+                None => return Some(cur),
             }
+        }
+    }
+}
 
-            let mut settled = true;
-            for dx in DIR_X {
-                let nx = cur_pos.x + dx;
-                let ny = cur_pos.y + 1;
-                if nx < 0 {
-                    continue;
-                }
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
+    let lines = common::parse(input);
 
-                let nx = nx as usize;
-                let mut ny = ny as usize;
-                if grid[nx][ny] == AIR {
-                    settled = false;
-
-                    // Jumps to the nearest vertically adjacent non-AIR cell
-                    while ny < grid[nx].len() - 1 {
-                        let nny = ny + 1;
-                        if grid[nx][nny] != AIR {
-                            break;
-                        }
-                        ny = nny;
-                    }
+    let rocks = process(&lines);
+
+    let abyss = SandConfig {
+        floor: None,
+        ..SandConfig::default()
+    };
+    let cfg = SandConfig::default();
+    let (mut cave, mut flooded) = match (Cave::new(&rocks, &abyss), Cave::new(&rocks, &cfg)) {
+        (Ok(cave), Ok(flooded)) => (cave, flooded),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("invalid setup: {}", e);
+            return;
+        }
+    };
+    // the grain-by-grain run starts from the same empty cave
+    let empty = flooded.clone();
+
+    println!("(p1) all-wall filled: {}", pour(&mut cave, &abyss));
+
+    let p2 = flood(&mut flooded, &cfg);
+    println!("(p2) flooded: {}", p2);
+
+    // the grain-by-grain pile when there is one
+    let pile = if args.iter().any(|a| a == "simulate") {
+        let mut cave = empty;
+        let cnt = pour(&mut cave, &cfg);
+        println!("(p2) simulated: {}", cnt);
+
+        let (poured, filled) = (cave.sand(), flooded.sand());
+        let diff = poured.symmetric_difference(&filled).count();
+        if cnt == p2 && diff == 0 {
+            println!("simulation matches flood fill");
+        } else {
+            println!("simulation differs from flood fill in {} cells", diff);
+        }
+        cave
+    } else {
+        flooded
+    };
 
-                    last_drop.push(Point {
-                        x: nx as i32,
-                        y: ny as i32,
-                    });
+    if args.iter().any(|a| a == "render") {
+        println!("{}", render(&pile, &cfg.sources));
+    }
+}
 
-                    break;
-                }
+/// Drops grains one at a time until every source is blocked or a grain
+/// falls into the abyss, returns how many came to rest
+fn pour(cave: &mut Cave, cfg: &SandConfig) -> usize {
+    // each source resumes from the path its last grain took
+    let mut paths: Vec<Vec<Point>> = cfg.sources.iter().map(|s| vec![*s]).collect();
+    let mut cnt = 0;
+
+    loop {
+        let mut dropped = false;
+        for path in paths.iter_mut() {
+            // grains from other sources may have landed on the path
+            if let Some(i) = path.iter().position(|p| cave.get(*p) != AIR) {
+                path.truncate(i);
+            }
+            if path.is_empty() {
+                continue;
             }
 
-            if settled {
-                cnt += 1;
-                last_drop.pop();
-                grid[cx][cy] = SAND
+            match cave.drop_grain(path, &cfg.fall_dx) {
+                Some(rest) => {
+                    cave.set(rest, SAND);
+                    path.pop();
+                    cnt += 1;
+                    dropped = true;
+                }
+                None => return cnt,
             }
         }
 
-        cnt
+        if !dropped {
+            return cnt;
+        }
     }
 }
 
-fn process(lines: &[String]) -> (VecDeque<Vec<u8>>, usize) {
-    let mut max_x = 0;
-    let mut max_y = 0;
+/// Fills every cell sand can reach from the sources, ignoring the order it
+/// falls in. Matches `pour` once the sources are blocked, which needs a
+/// floor: without one nothing below the lowest rock is filled.
+fn flood(cave: &mut Cave, cfg: &SandConfig) -> usize {
+    let mut last_drop: VecDeque<Point> = cfg.sources.iter().copied().collect();
+    let mut cnt = 0;
+
+    while let Some(cur) = last_drop.pop_front() {
+        if cave.get(cur) != AIR || cur.y >= cave.height() as i32 {
+            continue;
+        }
+
+        cave.set(cur, SAND);
+        cnt += 1;
+        for dx in cfg.fall_dx.iter() {
+            last_drop.push_back(Point {
+                x: cur.x + dx,
+                y: cur.y + 1,
+            });
+        }
+    }
+
+    cnt
+}
 
-    let mut walls: HashSet<(usize, usize)> = HashSet::new();
+fn process(lines: &[String]) -> HashSet<Point> {
+    let mut walls: HashSet<Point> = HashSet::new();
 
     for line in lines {
         let stone_paths: Vec<Point> = line
@@ -136,13 +309,10 @@ fn process(lines: &[String]) -> (VecDeque<Vec<u8>>, usize) {
             .map(|el| {
                 let nums: Vec<i32> = el
                     .trim()
-                    .split(",")
+                    .split(',')
                     .map(|num| num.parse::<i32>().unwrap())
                     .collect();
 
-                max_x = max_x.max(nums[0]);
-                max_y = max_y.max(nums[1]);
-
                 Point {
                     x: nums[0],
                     y: nums[1],
@@ -156,27 +326,36 @@ fn process(lines: &[String]) -> (VecDeque<Vec<u8>>, usize) {
 
             for y in (prev.y.min(cur.y))..=(prev.y.max(cur.y)) {
                 for x in (prev.x.min(cur.x))..=(prev.x.max(cur.x)) {
-                    walls.insert((x as usize, y as usize));
+                    walls.insert(Point { x, y });
                 }
             }
         }
     }
 
-    let max_y = max_y as usize + FLOOR_Y_REL;
-    let max_x = max_x as usize + 1;
-
-    let mut grid = VecDeque::new();
-    grid.resize(max_x * 2, vec![AIR; max_y + 1]);
-    grid.reserve((max_x * 2) as usize);
-    for wp in walls {
-        grid[wp.0][wp.1] = WALL;
-    }
-
-    for x in 0..max_x {
-        grid[x][max_y] = WALL;
-    }
-
-    if grid.len() > 0{}
+    walls
+}
 
-    (grid, max_y)
+/// Draws the cave like the puzzle does: `#` rock (and floor), `o` sand,
+/// `+` a source that is still open, `.` air
+fn render(cave: &Cave, sources: &[Point]) -> String {
+    let top = sources.iter().map(|s| s.y).min().unwrap_or(0).min(0);
+    let last = cave.floor.unwrap_or(cave.bottom);
+    let x1 = cave.x0 + cave.cols.len() as i32;
+
+    (top..=last)
+        .map(|y| {
+            (cave.x0..x1)
+                .map(|x| {
+                    let p = Point { x, y };
+                    match cave.get(p) {
+                        WALL => '#',
+                        SAND => 'o',
+                        _ if sources.contains(&p) => '+',
+                        _ => '.',
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
 }
@@ -1,101 +1,231 @@
-use std::io::{BufRead, Lines};
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    io::{BufRead, Lines},
+    ops::RangeInclusive,
+};
 
-const Y_MAX: isize = 4000000;
+const TUNING_X_MUL: isize = 4000000;
 
 struct SensorInfo {
     be_dist: isize,
     pos: [isize; 2],
+    /// Closest beacon, `be_dist` away
+    beacon: [isize; 2],
+}
+
+impl SensorInfo {
+    /// Whether the rotated point (`u`, `v`) is within `be_dist`
+    fn covers_rotated(&self, u: isize, v: isize) -> bool {
+        let su = self.pos[0] + self.pos[1];
+        let sv = self.pos[0] - self.pos[1];
+        (u - su).abs().max((v - sv).abs()) <= self.be_dist
+    }
+}
+
+/// Inclusive search area
+struct Rect {
+    min: [isize; 2],
+    max: [isize; 2],
+}
+
+/// Free part of `area` in rotated coordinates (u = x + y, v = x - y):
+/// every whole point with `u` and `v` in range that lies in `area`
+struct FreeCell {
+    u: [isize; 2],
+    v: [isize; 2],
+}
+
+impl FreeCell {
+    /// The cell's points, computed lazily as a cell can be huge
+    fn points<'a>(&'a self, area: &'a Rect) -> impl Iterator<Item = [isize; 2]> + 'a {
+        // x = (u + v) / 2 and y = (u - v) / 2 must stay in `area`
+        let [x0, y0] = area.min;
+        let [x1, y1] = area.max;
+        (self.u[0]..=self.u[1]).flat_map(move |u| {
+            let v_lo = self.v[0].max(2 * x0 - u).max(u - 2 * y1);
+            let v_hi = self.v[1].min(2 * x1 - u).min(u - 2 * y0);
+            // x and y are only whole when u and v have the same parity
+            let first_v = v_lo + (u - v_lo).rem_euclid(2);
+            (first_v..=v_hi)
+                .step_by(2)
+                .map(move |v| [(u + v) / 2, (u - v) / 2])
+        })
+    }
+}
+
+/// Rows to check and where to look for the distress beacon
+struct ScanConfig {
+    rows: RangeInclusive<isize>,
+    /// The beacon is searched in `0..=bound` on both axes
+    bound: isize,
+}
+
+/// Reason the scan can't be set up from the CLI args and input header
+enum ConfigError {
+    BadRow(String),
+    BadBound(String),
+    MissingRow,
+    MissingBound,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::BadRow(a) => write!(f, "`{}` is not a row or `from..=to` range", a),
+            ConfigError::BadBound(a) => write!(f, "`{}` is not a search bound", a),
+            ConfigError::MissingRow => {
+                write!(
+                    f,
+                    "no target row, pass `<row> <bound>` or add a `y <row>` header"
+                )
+            }
+            ConfigError::MissingBound => {
+                write!(
+                    f,
+                    "no search bound, pass `<row> <bound>` or add a `y <row>` header"
+                )
+            }
+        }
+    }
+}
+
+impl ScanConfig {
+    /// Reads `[row | from..=to] [bound]` from the extra CLI args. Missing
+    /// values come from the `y <row>` input header, with a bound of twice
+    /// that row like in both puzzle inputs. Without a header both are needed.
+    fn from_args(args: &[String], header_y: Option<isize>) -> Result<Self, ConfigError> {
+        let rows = match args.first() {
+            Some(a) => match a.split_once("..=") {
+                Some((from, to)) => from.parse().ok().zip(to.parse().ok()).map(|(f, t)| f..=t),
+                None => a.parse().ok().map(|y| y..=y),
+            }
+            .ok_or_else(|| ConfigError::BadRow(a.clone()))?,
+            None => header_y.map(|y| y..=y).ok_or(ConfigError::MissingRow)?,
+        };
+        let bound = match args.get(1) {
+            Some(a) => a
+                .parse::<isize>()
+                .map_err(|_| ConfigError::BadBound(a.clone()))?,
+            None => header_y.map(|y| y * 2).ok_or(ConfigError::MissingBound)?,
+        };
+
+        Ok(ScanConfig { rows, bound })
+    }
 }
 
 use crate::common;
 
-pub fn run(input: Lines<impl BufRead>) {
+pub fn run(input: Lines<impl BufRead>, args: &[String]) {
     let lines = common::parse(input);
-    let (sensors, pos_y) = process(&lines);
+    let (sensors, header_y) = match process(&lines) {
+        Ok(res) => res,
+        Err(e) => {
+            println!("invalid input: {}", e);
+            return;
+        }
+    };
+    let cfg = match ScanConfig::from_args(args, header_y) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("invalid args: {}", e);
+            return;
+        }
+    };
 
-    let segments = create_segments(pos_y, &sensors);
+    let coverage = rows_coverage(&sensors, cfg.rows.clone());
 
-    let mut covered_area = 0;
-    let mut prev_end = isize::MIN;
-    for sg in segments.iter() {
-        let width = sg[1] - sg[0].max(prev_end);
-        if width > 0 {
-            covered_area += width;
+    let search = Rect {
+        min: [0, 0],
+        max: [cfg.bound, cfg.bound],
+    };
+    let free = uncovered(&sensors, &search);
+
+    for (y, covered) in coverage {
+        println!("covered at y={y} : {covered}");
+    }
+    let mut beacons = 0;
+    for cell in free.iter() {
+        let pts: Vec<[isize; 2]> = cell.points(&search).take(2).collect();
+        match pts[..] {
+            [] => {}
+            [b] => {
+                beacons += 1;
+                println!("distress beacon at ({}, {})", b[0], b[1]);
+                println!("tuning frequency = {}", b[0] * TUNING_X_MUL + b[1]);
+            }
+            _ => println!(
+                "free area u={}..={} v={}..={}",
+                cell.u[0], cell.u[1], cell.v[0], cell.v[1]
+            ),
         }
-        prev_end = sg[1].max(prev_end);
     }
+    if beacons == 0 {
+        println!("no distress beacon in {:?}..={:?}", search.min, search.max);
+    }
+}
 
-    let mut distress_beacon = [-1_isize, -1_isize];
-    // trying to refactor using this approach
-    // https://www.reddit.com/r/adventofcode/comments/zmfwg1/2022_day_15_part_2_seekin_for_the_beacon
-    //
-    // and this
-    // https://github.com/tdpetrou/Advent-of-Code-Pandas/blob/master/2022/Problems.ipynb
-    let bounds = sensors
-        .iter()
-        .map(|s| {
-            let d = s.be_dist;
-            let top = [s.pos[0], s.pos[1] - d - 1];
-            let right = [s.pos[0] + d + 1, s.pos[1]];
-            let bottom = [s.pos[0], s.pos[1] + d + 1];
-            let left = [s.pos[0] - d - 1, s.pos[1]];
-
-            [top, right, bottom, left]
-        })
-        .collect::<Vec<_>>();
-
-    let perimeter_move: [[isize; 2]; 4] = [[1, 1], [-1, 1], [-1, -1], [1, -1]];
-
-    let in_radius = |p1: isize, p2: isize, d: isize| -> bool { (p1 - p2).abs() < d };
-    let point_valid = |x: isize, y: isize| -> bool { 0 <= x && x <= Y_MAX && 0 <= y && y <= Y_MAX };
-
-    for bi in 0..bounds.len() {
-        let sdist = sensors[bi].be_dist;
-        let dirs = &bounds[bi];
-
-        for (p, m) in dirs.iter().zip(perimeter_move.iter()) {
-            let mut pc = p.clone();
-
-            while point_valid(pc[0], pc[1]) && in_radius(pc[0], p[0], sdist) {
-                let mut mdist = sensors
-                    .iter()
-                    .map(|s| {
-                        let d_to_pc = (s.pos[0] - pc[0]).abs() + (s.pos[1] - pc[1]).abs();
-                        s.be_dist - d_to_pc
-                    })
-                    .max()
-                    .unwrap();
-
-                if mdist < 0 {
-                    distress_beacon[0] = pc[0];
-                    distress_beacon[1] = pc[1];
-                    break;
-                } else {
-                    // center/scaling factor for new pos
-                    mdist = (mdist / 2).max(1);
-                }
-                pc[0] = pc[0] + m[0] * mdist;
-                pc[1] = pc[1] + m[1] * mdist
+/// Parts of `area` no sensor covers, sorted by `u` then `v`.
+///
+/// Works in rotated coordinates (u = x + y, v = x - y) where each sensor
+/// covers an axis-aligned square. The lines along the square edges split
+/// the plane into cells that are either fully covered or fully free, so
+/// only one point per cell has to be checked. A lone free point, like the
+/// distress beacon, sits where a `u` line and a `v` line intersect.
+fn uncovered(sensors: &[SensorInfo], area: &Rect) -> Vec<FreeCell> {
+    // bounds of the area in rotated coordinates
+    let u_rng = [area.min[0] + area.min[1], area.max[0] + area.max[1]];
+    let v_rng = [area.min[0] - area.max[1], area.max[0] - area.min[1]];
+
+    // start of every cell, the last cell ends at `*_rng[1]`
+    let cuts = |rng: [isize; 2], center: fn(&SensorInfo) -> isize| -> Vec<isize> {
+        let mut cs = vec![rng[0]];
+        for s in sensors {
+            let c = center(s);
+            cs.extend([c - s.be_dist, c + s.be_dist + 1]);
+        }
+        cs.retain(|c| (rng[0]..=rng[1]).contains(c));
+        cs.sort_unstable();
+        cs.dedup();
+        cs
+    };
+    let us = cuts(u_rng, |s| s.pos[0] + s.pos[1]);
+    let vs = cuts(v_rng, |s| s.pos[0] - s.pos[1]);
+
+    let [x0, y0] = area.min;
+    let [x1, y1] = area.max;
+    let mut found = vec![];
+    for (ui, u0) in us.iter().enumerate() {
+        let u1 = us.get(ui + 1).map_or(u_rng[1], |u| u - 1);
+        for (vi, v0) in vs.iter().enumerate() {
+            let v1 = vs.get(vi + 1).map_or(v_rng[1], |v| v - 1);
+            if sensors.iter().any(|s| s.covers_rotated(*u0, *v0)) {
+                continue;
+            }
+
+            // shrink the cell to the part that overlaps `area`
+            let u = [
+                (*u0).max(v0 + 2 * y0).max(2 * x0 - v1),
+                u1.min(2 * x1 - v0).min(v1 + 2 * y1),
+            ];
+            let v = [
+                (*v0).max(2 * x0 - u[1]).max(u[0] - 2 * y1),
+                v1.min(2 * x1 - u[0]).min(u[1] - 2 * y0),
+            ];
+            if u[0] <= u[1] && v[0] <= v[1] {
+                found.push(FreeCell { u, v });
             }
         }
     }
 
-    println!("covered at y={pos_y} : {covered_area}");
-    println!(
-        "distress beacon at ({}, {})",
-        distress_beacon[0], distress_beacon[1]
-    );
-    println!(
-        "tuning frequency = {}",
-        distress_beacon[0] * Y_MAX + distress_beacon[1]
-    );
+    found
 }
 
+/// Covered x ranges of row `y`, inclusive, merged and sorted
 fn create_segments(pos_y: isize, sensors: &[SensorInfo]) -> Vec<[isize; 2]> {
     let mut segments = vec![];
 
-    for si in 0..sensors.len() {
-        let s = &sensors[si];
+    for s in sensors {
         let triangle_h = s.be_dist - (s.pos[1] - pos_y).abs();
         if triangle_h > -1 {
             // Add segment start-end x-coordinate
@@ -105,39 +235,101 @@ fn create_segments(pos_y: isize, sensors: &[SensorInfo]) -> Vec<[isize; 2]> {
     // Sort by lowest segment's start position
     segments.sort_by(|a, b| a[0].cmp(&b[0]));
 
-    segments
+    let mut merged: Vec<[isize; 2]> = vec![];
+    for sg in segments {
+        match merged.last_mut() {
+            // overlapping or touching
+            Some(last) if sg[0] <= last[1] + 1 => last[1] = last[1].max(sg[1]),
+            _ => merged.push(sg),
+        }
+    }
+
+    merged
+}
+
+/// Positions of row `y` where the distress beacon can't be: covered by a
+/// sensor and not holding one of the known beacons
+fn row_coverage(sensors: &[SensorInfo], y: isize) -> isize {
+    let segments = create_segments(y, sensors);
+    let covered: isize = segments.iter().map(|sg| sg[1] - sg[0] + 1).sum();
+
+    // several sensors can share a beacon
+    let beacons: BTreeSet<isize> = sensors
+        .iter()
+        .filter(|s| s.beacon[1] == y)
+        .map(|s| s.beacon[0])
+        .filter(|x| segments.iter().any(|sg| (sg[0]..=sg[1]).contains(x)))
+        .collect();
+
+    covered - beacons.len() as isize
+}
+
+/// `(y, row_coverage)` for every row of `ys`
+fn rows_coverage(sensors: &[SensorInfo], ys: RangeInclusive<isize>) -> Vec<(isize, isize)> {
+    ys.map(|y| (y, row_coverage(sensors, y))).collect()
 }
 
-fn process(lines: &[String]) -> (Vec<SensorInfo>, isize) {
+/// Reason the input can't be parsed, `line` is 1-based
+enum ParseError {
+    BadHeader,
+    BadSensor { line: usize },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::BadHeader => write!(f, "line 1: expected `y <row>`"),
+            ParseError::BadSensor { line } => write!(
+                f,
+                "line {}: expected `Sensor at x=<x>, y=<y>: closest beacon is at x=<x>, y=<y>`",
+                line
+            ),
+        }
+    }
+}
+
+/// Also returns the row of the optional `y <row>` header line
+fn process(lines: &[String]) -> Result<(Vec<SensorInfo>, Option<isize>), ParseError> {
     let mut beacon_dist: Vec<SensorInfo> = vec![];
 
-    let objective_y = lines[0]
-        .split(" ")
-        .last()
-        .map_or(-1, |s| s.trim().parse::<isize>().unwrap());
-
-    for li in 1..lines.len() {
-        let mut sen_bea = lines[li].split(":").map(|s| {
-            let mut coords = s
-                .trim()
-                .split("at")
-                .last()
-                .unwrap()
-                .split(",")
-                .map(|coord| coord.split("=").last().unwrap().parse::<isize>().unwrap());
-
-            [coords.next().unwrap(), coords.next().unwrap()]
-        });
+    let objective_y = match lines.first().and_then(|l| l.strip_prefix("y ")) {
+        Some(y) => Some(
+            y.trim()
+                .parse::<isize>()
+                .map_err(|_| ParseError::BadHeader)?,
+        ),
+        None => None,
+    };
 
-        let sensor = sen_bea.next().unwrap();
-        let beacon = sen_bea.next().unwrap();
+    let header_len = usize::from(objective_y.is_some());
+    for (i, line) in lines.iter().enumerate().skip(header_len) {
+        let (sensor, beacon) = parse_sensor(line).ok_or(ParseError::BadSensor { line: i + 1 })?;
         let mdist = sensor[0].abs_diff(beacon[0]) + sensor[1].abs_diff(beacon[1]);
 
         beacon_dist.push(SensorInfo {
             pos: sensor,
             be_dist: mdist as isize,
+            beacon,
         });
     }
 
-    (beacon_dist, objective_y)
+    Ok((beacon_dist, objective_y))
+}
+
+/// Sensor and beacon positions of a `Sensor at x=.., y=..: closest beacon is
+/// at x=.., y=..` line
+fn parse_sensor(line: &str) -> Option<([isize; 2], [isize; 2])> {
+    let coords = |s: &str| -> Option<[isize; 2]> {
+        let (x, y) = s.trim().split_once(", ")?;
+        Some([
+            x.strip_prefix("x=")?.parse().ok()?,
+            y.strip_prefix("y=")?.parse().ok()?,
+        ])
+    };
+
+    let (sensor, beacon) = line
+        .strip_prefix("Sensor at ")?
+        .split_once(": closest beacon is at ")?;
+
+    Some((coords(sensor)?, coords(beacon)?))
 }
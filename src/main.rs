@@ -36,7 +36,10 @@ fn read_file(path: &str) -> BufReader<File> {
 }
 
 fn main() {
-    let d = env::args().nth(1).or(Some(LATEST_DAY.into())).unwrap();
+    let args: Vec<String> = env::args().collect();
+    let d = args.get(1).cloned().unwrap_or(LATEST_DAY.into());
+    // everything after the day is passed to the solution as-is
+    let extra_args = args.get(2..).unwrap_or(&[]);
 
     println!("Running Day-{}", d);
     println!("{:=>10}", "");
@@ -81,7 +84,7 @@ fn main() {
         "7" => {
             // Day-7
             let input = read_file("input-d7");
-            d07::run(input.lines());
+            d07::run(input.lines(), extra_args);
         }
 
         "8" => {
@@ -93,21 +96,21 @@ fn main() {
         "9" => {
             // Day-9
             let input = read_file("input-d9");
-            d09::run(input.lines());
+            d09::run(input.lines(), extra_args);
         }
 
         "10" => {
             // Day-10
             // let input = read_file("input-d10-sample");
             let input = read_file("input-d10");
-            d10::run(input.lines());
+            d10::run(input.lines(), extra_args);
         }
 
         "11" => {
             // Day-11
             // let input = read_file("input-d11-sample");
             let input = read_file("input-d11");
-            d11::run(input.lines());
+            d11::run(input.lines(), extra_args);
         }
 
         "12" => {
@@ -121,21 +124,21 @@ fn main() {
             // Day-13
             // let input = read_file("input-d13-sample");
             let input = read_file("input-d13");
-            d13::run(input.lines());
+            d13::run(input.lines(), extra_args);
         }
 
         "14" => {
             // Day-14
             // let input = read_file("input-d14-sample");
             let input = read_file("input-d14");
-            d14::run(input.lines());
+            d14::run(input.lines(), extra_args);
         }
 
         "15" => {
             // Day-15
             // let input = read_file("input-d15-sample");
             let input = read_file("input-d15");
-            d15::run(input.lines());
+            d15::run(input.lines(), extra_args);
         }
 
         "16" => {